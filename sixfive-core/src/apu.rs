@@ -0,0 +1,318 @@
+// The sound chip's accurate mode: rather than working out envelopes and note lengths from the
+// time since each note started, everything is clocked from a NES-like CPU clock, with a frame
+// sequencer stepping the envelopes, sweeps and length counters the way the hardware does.
+// Each host sample is the average of the chip's output over the cycles it covers.
+
+use crate::sound::{clock_shift_register, ChannelRegisters};
+
+pub const CPU_FREQUENCY: f64 = 1_789_773.0;
+
+// Length counter loads, by note length (register 3)
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// By duty cycle (12.5%, 25%, 50%, 75%)
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// CPU cycles at which each frame sequencer step happens, and whether it's a half frame
+// (which clocks lengths and sweeps, as well as envelopes)
+const FOUR_STEP_SEQUENCE: [(u32, bool); 4] =
+    [(7457, false), (14913, true), (22371, false), (29829, true)];
+const FIVE_STEP_SEQUENCE: [(u32, bool); 4] =
+    [(7457, false), (14913, true), (22371, false), (37281, true)];
+const FOUR_STEP_LENGTH: u32 = 29830;
+const FIVE_STEP_LENGTH: u32 = 37282;
+
+#[derive(Clone, Default)]
+struct FrameSequencer {
+    five_step: bool,
+    cycle: u32,
+}
+
+impl FrameSequencer {
+    // Returns whether this cycle is a quarter frame, and whether it's a half frame
+    fn clock(&mut self) -> (bool, bool) {
+        let (sequence, length) = if self.five_step {
+            (FIVE_STEP_SEQUENCE, FIVE_STEP_LENGTH)
+        } else {
+            (FOUR_STEP_SEQUENCE, FOUR_STEP_LENGTH)
+        };
+
+        self.cycle = (self.cycle + 1) % length;
+
+        match sequence.iter().find(|(cycle, _)| *cycle == self.cycle) {
+            Some((_, half)) => (true, *half),
+            None => (false, false),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Envelope {
+    start: bool,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // Clocked every quarter frame
+    fn clock(&mut self, registers: &ChannelRegisters) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = registers.envelope_length;
+        } else if self.divider == 0 {
+            self.divider = registers.envelope_length;
+
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if registers.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    // 0 ..= 15
+    fn volume(&self, registers: &ChannelRegisters) -> u8 {
+        if registers.envelope {
+            self.decay
+        } else {
+            registers.envelope_length
+        }
+    }
+}
+
+// Timer, sequencer, envelope, length counter and sweep for a single channel
+#[derive(Clone, Default)]
+struct ChannelState {
+    timer: u16,
+    step: usize,
+    // The timer's period, which the sweep unit changes without touching the registers
+    period: u16,
+    envelope: Envelope,
+    length: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl ChannelState {
+    // Counts down, returning true when the timer wraps around (and the sequencer should step)
+    fn clock_timer(&mut self) -> bool {
+        if self.timer == 0 {
+            self.timer = self.period;
+            true
+        } else {
+            self.timer -= 1;
+            false
+        }
+    }
+
+    fn clock_length(&mut self, registers: &ChannelRegisters) {
+        // The loop flag also halts the length counter, so notes hold indefinitely
+        if !registers.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    // Clocked every half frame. The divider counts 2 ^ shift speed half frames between steps.
+    fn clock_sweep(&mut self, registers: &ChannelRegisters, ones_complement: bool) {
+        if self.sweep_divider == 0
+            && registers.shift_enabled
+            && registers.shift_period > 0
+            && !registers.sweep_muted(self.period, ones_complement)
+        {
+            self.period = registers.sweep_target(self.period, ones_complement);
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = (1 << registers.shift_speed) - 1;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Apu {
+    frame_sequencer: FrameSequencer,
+    // Square wave 1, square wave 2, triangle wave, noise
+    channels: [ChannelState; 4],
+    shift_register: u16,
+    // Pulse timers are only clocked on even cycles
+    odd_cycle: bool,
+    // Fraction of a CPU cycle left over from the last sample
+    cycles_remaining: f64,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self {
+            frame_sequencer: FrameSequencer::default(),
+            channels: Default::default(),
+            shift_register: 1,
+            odd_cycle: false,
+            cycles_remaining: 0.0,
+        }
+    }
+}
+
+impl Apu {
+    pub fn five_step(&self) -> bool {
+        self.frame_sequencer.five_step
+    }
+
+    // Restarts the sequence. Like the hardware, the 5-step sequence clocks everything straight away.
+    pub fn set_five_step(&mut self, five_step: bool, registers: &[ChannelRegisters; 4]) {
+        self.frame_sequencer = FrameSequencer {
+            five_step,
+            cycle: 0,
+        };
+
+        if five_step {
+            self.clock_frame(true, true, registers);
+        }
+    }
+
+    // The channel's period after sweeps, and whether the sweep unit has muted it
+    pub fn sweep_state(&self, channel: usize, registers: &ChannelRegisters) -> (u16, bool) {
+        match channel {
+            // The noise channel's period is a table index, rather than the timer's reload value
+            3 => (registers.period, false),
+            _ => {
+                let period = self.channels[channel].period;
+                let muted = registers.sweeps() && registers.sweep_muted(period, channel == 0);
+                (period, muted)
+            }
+        }
+    }
+
+    // Called after a channel's register is written, to restart whatever the write affects
+    pub fn write(&mut self, channel: usize, register: u8, registers: &ChannelRegisters) {
+        let state = &mut self.channels[channel];
+
+        // Noise periods come from a table, and are in CPU cycles rather than timer reloads
+        let period = if channel == 3 {
+            registers.noise_period() - 1
+        } else {
+            registers.period
+        };
+
+        match register {
+            0x01 => state.sweep_reload = true,
+            0x02 => state.period = period,
+            0x03 => {
+                state.period = period;
+                state.length = LENGTH_TABLE[registers.note_length as usize];
+                state.envelope.start = true;
+
+                // Pulse channels restart their duty cycle with each note
+                if channel < 2 {
+                    state.step = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_frame(&mut self, quarter: bool, half: bool, registers: &[ChannelRegisters; 4]) {
+        for (channel, (state, registers)) in self.channels.iter_mut().zip(registers).enumerate() {
+            if quarter {
+                state.envelope.clock(registers);
+            }
+
+            if half {
+                state.clock_length(registers);
+
+                if registers.sweeps() {
+                    state.clock_sweep(registers, channel == 0);
+                }
+            }
+        }
+    }
+
+    // Output of each channel for this cycle, from 0.0 to 1.0
+    fn output(&self, registers: &[ChannelRegisters; 4]) -> [f32; 4] {
+        let mut output = [0.0; 4];
+
+        for (channel, (state, registers)) in self.channels.iter().zip(registers).enumerate() {
+            if state.length == 0
+                || (registers.sweeps() && registers.sweep_muted(state.period, channel == 0))
+            {
+                continue;
+            }
+
+            let level = match channel {
+                0 | 1 => DUTY_SEQUENCES[registers.duty_cycle as usize][state.step] * 15,
+                2 => TRIANGLE_SEQUENCE[state.step],
+                _ => (1 - (self.shift_register & 0b1) as u8) * 15,
+            };
+
+            output[channel] =
+                (level as f32 / 15.0) * (state.envelope.volume(registers) as f32 / 15.0);
+        }
+
+        output
+    }
+
+    fn clock(&mut self, registers: &[ChannelRegisters; 4]) {
+        let (quarter, half) = self.frame_sequencer.clock();
+        if quarter {
+            self.clock_frame(quarter, half, registers);
+        }
+
+        // Pulse timers count every other CPU cycle, which makes them match the simple mode's pitch
+        if !self.odd_cycle {
+            for state in &mut self.channels[0..2] {
+                if state.clock_timer() {
+                    state.step = (state.step + 1) % 8;
+                }
+            }
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        // The triangle is clocked twice as fast as on the NES, for the same reason
+        let triangle = &mut self.channels[2];
+        for _ in 0..2 {
+            if triangle.clock_timer() {
+                triangle.step = (triangle.step + 1) % 32;
+            }
+        }
+
+        if self.channels[3].clock_timer() {
+            clock_shift_register(&mut self.shift_register, registers[3].noise_short_mode());
+        }
+    }
+
+    // Runs the chip for one host sample, returning each channel's average output
+    pub fn generate(&mut self, sample_rate: f64, registers: &[ChannelRegisters; 4]) -> [f32; 4] {
+        self.cycles_remaining += CPU_FREQUENCY / sample_rate;
+        let cycles = self.cycles_remaining as u64;
+        self.cycles_remaining -= cycles as f64;
+
+        let mut total = [0.0; 4];
+        for _ in 0..cycles {
+            self.clock(registers);
+
+            for (total, output) in total.iter_mut().zip(self.output(registers)) {
+                *total += output;
+            }
+        }
+
+        total.map(|total| total / cycles.max(1) as f32)
+    }
+}
@@ -0,0 +1,412 @@
+// Assembles source written the way patches/fib.txt is annotated:
+//
+//         .org $10
+//     loop:
+//         LOAD $90        ; value read from memory
+//         ADD  #$01       ; immediate value
+//         STOR ($9F)      ; address read from memory
+//         BREQ done       ; labels can be used anywhere an address or value can
+//         JUMP loop
+//
+// Indexed operands are written $nn,X / $nn,Y / ($nn),Y, and AWI0 ... AWIF take #$nn or $nn.
+// The result is a single bank: 64 instruction words, as stored in the plugin's ROM banks.
+
+use std::{collections::HashMap, fmt};
+
+use crate::isa::{
+    self, Instruction, OperandKind, INDEXED_BY_X, INDEXED_BY_Y, INDEXED_OPERATIONS,
+    INDIRECT_INDEXED_BY_Y,
+};
+
+pub const BANK_LENGTH: usize = 0x40;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssemblerError {
+    // Counted from 1, like an editor would
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Index {
+    X,
+    Y,
+    // ($nn),Y
+    IndirectY,
+}
+
+struct Operand<'a> {
+    immediate: bool,
+    // Wrapped in parentheses, for reading an address from memory
+    indirect: bool,
+    index: Option<Index>,
+    value: &'a str,
+}
+
+struct Statement<'a> {
+    line: usize,
+    address: u8,
+    instruction: &'static Instruction,
+    operand: Option<String>,
+    mnemonic: &'a str,
+}
+
+fn strip_parentheses(text: &str) -> (bool, &str) {
+    let inner = match text
+        .strip_prefix('(')
+        .and_then(|text| text.strip_suffix(')'))
+    {
+        Some(inner) => inner,
+        None => return (false, text),
+    };
+
+    // Make sure these parentheses actually wrap the whole thing, and not "($10),(Y)"
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return (false, text),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    (true, inner)
+}
+
+fn parse_operand(text: &str) -> Result<Operand<'_>, String> {
+    let (immediate, text) = match text.strip_prefix('#') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+
+    let (indirect, text) = strip_parentheses(text);
+
+    let strip_index = |upper, lower| text.strip_suffix(upper).or(text.strip_suffix(lower));
+    let (index, text) = match (strip_index(",X", ",x"), strip_index(",Y", ",y")) {
+        (Some(text), _) => (Some(Index::X), text),
+        (_, Some(text)) => (Some(Index::Y), text),
+        _ => (None, text),
+    };
+
+    // ($nn),Y: the parentheses only wrap the address
+    let (index, text) = match (index, strip_parentheses(text)) {
+        (Some(Index::Y), (true, inner)) => (Some(Index::IndirectY), inner),
+        (_, (true, _)) => return Err("only ($nn),Y can be indirect and indexed".to_string()),
+        (index, (false, text)) => (index, text),
+    };
+
+    if immediate && indirect {
+        return Err("an operand can't be both immediate and indirect".to_string());
+    }
+
+    Ok(Operand {
+        immediate,
+        indirect,
+        index,
+        value: text,
+    })
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(text: &str, labels: &HashMap<&str, u8>) -> Result<u8, String> {
+    if let Some(hex) = text.strip_prefix('$') {
+        return u8::from_str_radix(hex, 16).map_err(|_| format!("{} isn't a byte in hex", text));
+    }
+
+    if is_label(text) {
+        return labels
+            .get(text)
+            .copied()
+            .ok_or(format!("unknown label {}", text));
+    }
+
+    Err(format!("expected $nn or a label, found {}", text))
+}
+
+fn encode(statement: &Statement, labels: &HashMap<&str, u8>) -> Result<u16, String> {
+    let instruction = statement.instruction;
+    let mnemonic = statement.mnemonic;
+
+    let operand = match &statement.operand {
+        Some(operand) => parse_operand(operand)?,
+        None if instruction.operand == OperandKind::Implied => {
+            return Ok((instruction.opcode as u16) << 8)
+        }
+        None => return Err(format!("{} needs an operand", mnemonic)),
+    };
+
+    let argument = parse_value(operand.value, labels)?;
+
+    // Whether to use the odd (absolute) version of the opcode
+    let absolute = match instruction.operand {
+        OperandKind::Implied | OperandKind::Value | OperandKind::Sound => {
+            if operand.indirect {
+                return Err(format!("{} takes #$nn or $nn", mnemonic));
+            }
+            !operand.immediate
+        }
+        OperandKind::Address => {
+            if operand.immediate {
+                return Err(format!("{} takes an address, $nn or ($nn)", mnemonic));
+            }
+            operand.indirect
+        }
+    };
+
+    let opcode = match operand.index {
+        Some(index) => {
+            let position = INDEXED_OPERATIONS
+                .iter()
+                .position(|opcode| *opcode == instruction.opcode)
+                .ok_or(format!("{} can't be indexed", mnemonic))?;
+
+            let base = match index {
+                Index::X => INDEXED_BY_X,
+                Index::Y => INDEXED_BY_Y,
+                Index::IndirectY => INDIRECT_INDEXED_BY_Y,
+            };
+
+            base | (position as u8) << 1 | absolute as u8
+        }
+        None => match instruction.operand {
+            OperandKind::Sound if absolute => instruction.opcode + 0x10,
+            OperandKind::Sound => instruction.opcode,
+            _ => instruction.opcode | absolute as u8,
+        },
+    };
+
+    Ok((opcode as u16) << 8 | argument as u16)
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u16>, Vec<AssemblerError>> {
+    let mut errors = Vec::new();
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+
+    // The line that put an instruction in each word, so .org can't silently overwrite it
+    let mut written_by = [None; BANK_LENGTH];
+
+    // First pass: work out where everything goes, so labels can be used before they're defined
+    let mut address: usize = 0;
+    for (number, line) in source.lines().enumerate() {
+        let line_number = number + 1;
+        let mut error = |message: String| {
+            errors.push(AssemblerError {
+                line: line_number,
+                message,
+            })
+        };
+
+        // Everything after a semicolon is a comment
+        let mut text = line.split(';').next().unwrap().trim();
+
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                error(format!("{} isn't a valid label", label));
+                continue;
+            }
+            if labels.insert(label, address as u8).is_some() {
+                error(format!("label {} is defined more than once", label));
+            }
+            text = rest.trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operand) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, operand)) => (mnemonic, Some(operand)),
+            None => (text, None),
+        };
+        // Spaces within an operand don't matter: "( $9F ), Y" is ($9F),Y
+        let operand = operand.map(|operand| operand.split_whitespace().collect::<String>());
+
+        if mnemonic.eq_ignore_ascii_case(".org") {
+            match operand
+                .as_deref()
+                .map(|operand| parse_value(operand, &HashMap::new()))
+            {
+                Some(Ok(origin)) if origin & 0b1 == 0 && origin < 0x80 => address = origin as usize,
+                Some(Ok(origin)) => error(format!(
+                    ".org 0x{:02X} isn't an even address in ROM",
+                    origin
+                )),
+                Some(Err(message)) => error(message),
+                None => error(".org needs an address".to_string()),
+            }
+            continue;
+        }
+
+        let instruction = match isa::find_mnemonic(mnemonic) {
+            Some(instruction) => instruction,
+            None => {
+                error(format!("unknown instruction {}", mnemonic));
+                continue;
+            }
+        };
+
+        if address >= BANK_LENGTH * 2 {
+            error("program doesn't fit in ROM".to_string());
+            continue;
+        }
+
+        if let Some(line) = written_by[address / 2] {
+            error(format!("overlaps line {}", line));
+            continue;
+        }
+        written_by[address / 2] = Some(line_number);
+
+        statements.push(Statement {
+            line: line_number,
+            address: address as u8,
+            instruction,
+            operand,
+            mnemonic,
+        });
+        address += 2;
+    }
+
+    // Second pass: encode everything, now that all the labels are known
+    let mut bank = vec![0; BANK_LENGTH];
+    for statement in &statements {
+        match encode(statement, &labels) {
+            Ok(word) => bank[statement.address as usize / 2] = word,
+            Err(message) => errors.push(AssemblerError {
+                line: statement.line,
+                message,
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(bank)
+    } else {
+        errors.sort_by_key(|error| error.line);
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rebuilds fib.txt's program from its annotated listing, keeping each "; 0xNN" as an .org
+    #[test]
+    fn fib_matches_its_listing() {
+        let patch = include_str!("../../patches/fib.txt");
+
+        let mut expected = vec![0; BANK_LENGTH];
+        let mut source = String::new();
+        for line in patch.lines() {
+            if let Some((address, words)) = line.strip_prefix("0x").and_then(|l| l.split_once(':'))
+            {
+                let address = usize::from_str_radix(address, 16).unwrap();
+                for (i, word) in words.split_whitespace().enumerate() {
+                    expected[address / 2 + i] = u16::from_str_radix(word, 16).unwrap();
+                }
+            } else if let Some(origin) = line.strip_prefix("; 0x") {
+                source += &format!(".org ${}\n", origin);
+            } else if let Some((word, code)) = line.split_once("  ") {
+                if u16::from_str_radix(word, 16).is_ok() {
+                    source += code;
+                    source += "\n";
+                }
+            }
+        }
+
+        assert_eq!(assemble(&source), Ok(expected));
+    }
+
+    #[test]
+    fn labels_and_addressing_modes() {
+        let source = "
+                .org $10
+            loop:
+                LOAD $90,X      ; indexed
+                ADD  #$01
+                STOR ( $9F ), y
+                AWI3 #$20
+                AWI3 $20
+                BREQ done
+                JUMP loop
+            done:
+                HALT
+        ";
+
+        let bank = assemble(source).unwrap();
+        let listing: Vec<_> = bank[0x08..0x10]
+            .iter()
+            .map(|word| isa::disassemble(*word).unwrap())
+            .collect();
+
+        assert_eq!(
+            listing,
+            [
+                "LOAD $90,X",
+                "ADD #$01",
+                "STOR ($9F),Y",
+                "AWI3 #$20",
+                "AWI3 $20",
+                "BREQ $1E",
+                "JMP $10",
+                "HALT",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        let errors =
+            assemble("LOAD #$01\nFOO $10\nSTOR #$10\nJMP nowhere\nLOAD #$100").unwrap_err();
+        let lines: Vec<_> = errors.iter().map(|error| error.line).collect();
+
+        assert_eq!(lines, [2, 3, 4, 5]);
+        assert_eq!(errors[0].message, "unknown instruction FOO");
+        assert_eq!(errors[2].message, "unknown label nowhere");
+    }
+
+    #[test]
+    fn non_ascii_operands_are_errors() {
+        assert!(assemble("LOAD $é").is_err());
+        assert!(assemble("LOAD é,").is_err());
+        assert!(assemble("STOR ($€),Y").is_err());
+    }
+
+    #[test]
+    fn org_cant_overwrite_code() {
+        let errors = assemble("LOAD #$01\nSTOR $80\n.org $02\nHALT").unwrap_err();
+
+        assert_eq!(
+            errors,
+            [AssemblerError {
+                line: 4,
+                message: "overlaps line 2".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn programs_must_fit_in_rom() {
+        let source = "HALT\n".repeat(BANK_LENGTH + 1);
+        let errors = assemble(&source).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, BANK_LENGTH + 1);
+    }
+}
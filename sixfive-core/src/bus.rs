@@ -0,0 +1,273 @@
+use std::{fmt, ops::RangeInclusive};
+
+use crate::{machine::CpuFault, sound::FRAME_COUNTER};
+
+// A device on the memory bus. Addresses are passed through unchanged (not relative to
+// where the device is mapped), so a device can tell its registers apart however it likes.
+// (Built-in devices mapped away from home are the exception: see `Bus::map`.)
+pub trait Peripheral: Send {
+    // Reads shouldn't have side effects, since the debugger and editor read memory too
+    fn read(&self, address: u8) -> Result<u8, CpuFault>;
+
+    fn write(&mut self, address: u8, value: u8) -> Result<(), CpuFault>;
+
+    // Called once per CPU clock (including clocks spent waiting), for devices like timers
+    // that need to keep time with the CPU
+    fn tick(&mut self) {}
+}
+
+// Plain memory is indexed by address modulo its size, so it has to be mapped somewhere
+// aligned to its size (RAM at 0x80, the stack at 0xE0)
+impl<const N: usize> Peripheral for [u8; N] {
+    fn read(&self, address: u8) -> Result<u8, CpuFault> {
+        Ok(self[address as usize % N])
+    }
+
+    fn write(&mut self, address: u8, value: u8) -> Result<(), CpuFault> {
+        self[address as usize % N] = value;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Device {
+    Unmapped,
+    // Read-only, and depend on the machine's current bank (so the machine handles them itself)
+    Rom,
+    TrampolineVectors,
+    // Bank register and fault registers, which live in the CPU
+    System,
+    Ram,
+    Sound,
+    Interrupts,
+    Stack,
+    // Index into the bus's list of added peripherals
+    Custom(usize),
+}
+
+// Returned when mapping a device over addresses that something else already answers at
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AddressConflict {
+    pub address: u8,
+    pub device: Device,
+}
+
+impl fmt::Display for AddressConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:02X} is already mapped to {:?}",
+            self.address, self.device
+        )
+    }
+}
+
+// Where the built-in devices answer by default
+const BUILT_IN_DEVICES: [(RangeInclusive<u8>, Device); 8] = [
+    (0x00..=0x7F, Device::Rom),
+    (0x80..=0x9F, Device::Ram),
+    (0xA0..=0xAF, Device::Sound),
+    (0xB0..=0xB4, Device::Interrupts),
+    (FRAME_COUNTER..=FRAME_COUNTER, Device::Sound),
+    (0xB8..=0xBB, Device::System),
+    (0xE0..=0xEF, Device::Stack),
+    (0xFC..=0xFF, Device::TrampolineVectors),
+];
+
+// Which device answers at each address. The built-in devices are set up by default;
+// anything else can be added into the unmapped gaps (0xB6..=0xB7, 0xBC..=0xDF, 0xF0..=0xFB),
+// or in place of a built-in device once that's unmapped.
+pub struct Bus {
+    map: [Device; 0x100],
+    // The address each device sees, which differs from the CPU's for mirrors
+    device_addresses: [u8; 0x100],
+    peripherals: Vec<Box<dyn Peripheral>>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        let mut bus = Self {
+            map: [Device::Unmapped; 0x100],
+            device_addresses: std::array::from_fn(|address| address as u8),
+            peripherals: Vec::new(),
+        };
+
+        for (addresses, device) in BUILT_IN_DEVICES {
+            bus.map(addresses, device)
+                .expect("built-in devices shouldn't overlap");
+        }
+
+        bus
+    }
+}
+
+impl Bus {
+    // Maps a device (built-in or added) into a free range of addresses. Built-in devices
+    // can be mapped more than once, to mirror them somewhere else: a mirror sees the addresses
+    // of the device's first home range (repeating them if it's bigger), so the device still
+    // gets the addresses it expects.
+    pub fn map(
+        &mut self,
+        addresses: RangeInclusive<u8>,
+        device: Device,
+    ) -> Result<(), AddressConflict> {
+        for address in addresses.clone() {
+            let existing = self.map[address as usize];
+            if existing != Device::Unmapped {
+                return Err(AddressConflict {
+                    address,
+                    device: existing,
+                });
+            }
+        }
+
+        let home = BUILT_IN_DEVICES
+            .into_iter()
+            .find(|(_, built_in)| *built_in == device)
+            .map(|(home, _)| home);
+
+        for (offset, address) in addresses.enumerate() {
+            let at_home = BUILT_IN_DEVICES
+                .into_iter()
+                .any(|(home, built_in)| built_in == device && home.contains(&address));
+
+            self.map[address as usize] = device;
+            self.device_addresses[address as usize] = match &home {
+                Some(home) if !at_home => home.start() + (offset % home.len()) as u8,
+                _ => address,
+            };
+        }
+
+        Ok(())
+    }
+
+    // Frees up a range of addresses, so that something else can be mapped there
+    pub fn unmap(&mut self, addresses: RangeInclusive<u8>) {
+        for address in addresses {
+            self.map[address as usize] = Device::Unmapped;
+            self.device_addresses[address as usize] = address;
+        }
+    }
+
+    pub fn device(&self, address: u8) -> Device {
+        self.map[address as usize]
+    }
+
+    // The device at an address, and the address it sees there
+    pub fn resolve(&self, address: u8) -> (Device, u8) {
+        (
+            self.map[address as usize],
+            self.device_addresses[address as usize],
+        )
+    }
+
+    // Maps a new device into a free range of addresses
+    pub fn add_peripheral(
+        &mut self,
+        addresses: RangeInclusive<u8>,
+        peripheral: Box<dyn Peripheral>,
+    ) -> Result<Device, AddressConflict> {
+        let device = Device::Custom(self.peripherals.len());
+        self.map(addresses, device)?;
+        self.peripherals.push(peripheral);
+        Ok(device)
+    }
+
+    pub fn peripheral(&self, index: usize) -> &dyn Peripheral {
+        self.peripherals[index].as_ref()
+    }
+
+    pub fn peripheral_mut(&mut self, index: usize) -> &mut dyn Peripheral {
+        self.peripherals[index].as_mut()
+    }
+
+    // The built-in devices are clocked by the machine itself
+    pub fn tick(&mut self) {
+        for peripheral in &mut self.peripherals {
+            peripheral.tick();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts clocks, and reads back the count
+    #[derive(Default)]
+    struct Timer(u8);
+
+    impl Peripheral for Timer {
+        fn read(&self, _address: u8) -> Result<u8, CpuFault> {
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _address: u8, value: u8) -> Result<(), CpuFault> {
+            self.0 = value;
+            Ok(())
+        }
+
+        fn tick(&mut self) {
+            self.0 = self.0.wrapping_add(1);
+        }
+    }
+
+    #[test]
+    fn overlapping_peripherals_are_rejected() {
+        let mut bus = Bus::default();
+
+        assert_eq!(
+            bus.add_peripheral(0x9E..=0xA1, Box::<Timer>::default()),
+            Err(AddressConflict {
+                address: 0x9E,
+                device: Device::Ram
+            })
+        );
+        // Nothing is left half-mapped
+        assert_eq!(bus.device(0xA0), Device::Sound);
+
+        assert_eq!(
+            bus.add_peripheral(0xC0..=0xC0, Box::<Timer>::default()),
+            Ok(Device::Custom(0))
+        );
+    }
+
+    #[test]
+    fn built_in_devices_can_be_replaced() {
+        let mut bus = Bus::default();
+        bus.unmap(0xE0..=0xEF);
+
+        let device = bus.add_peripheral(0xE0..=0xE0, Box::<Timer>::default());
+        assert_eq!(device, Ok(Device::Custom(0)));
+        assert_eq!(bus.device(0xE1), Device::Unmapped);
+
+        // And mapped somewhere else
+        assert_eq!(bus.map(0xC0..=0xCF, Device::Stack), Ok(()));
+    }
+
+    #[test]
+    fn mirrors_see_home_addresses() {
+        let mut bus = Bus::default();
+        assert_eq!(bus.map(0xC0..=0xC7, Device::Sound), Ok(()));
+        assert_eq!(bus.map(0xD0..=0xD7, Device::TrampolineVectors), Ok(()));
+
+        assert_eq!(bus.resolve(0xC5), (Device::Sound, 0xA5));
+        // Bigger than the device, so it repeats
+        assert_eq!(bus.resolve(0xD5), (Device::TrampolineVectors, 0xFD));
+        // The home ranges are unchanged, including the frame counter away from the others
+        assert_eq!(bus.resolve(0xA5), (Device::Sound, 0xA5));
+        assert_eq!(bus.resolve(FRAME_COUNTER), (Device::Sound, FRAME_COUNTER));
+    }
+
+    #[test]
+    fn peripherals_are_ticked() {
+        let mut bus = Bus::default();
+        bus.add_peripheral(0xC0..=0xC0, Box::<Timer>::default())
+            .unwrap();
+
+        for _ in 0..3 {
+            bus.tick();
+        }
+        assert_eq!(bus.peripheral(0).read(0xC0).ok(), Some(3));
+    }
+}
@@ -0,0 +1,175 @@
+#[derive(Clone, Copy, PartialEq)]
+pub enum Breakpoint {
+    Always,
+    // Only pause if the accumulator holds this value when the instruction is reached
+    AccumulatorEquals(u8),
+}
+
+impl Breakpoint {
+    fn hit(&self, accumulator: u8) -> bool {
+        match self {
+            Breakpoint::Always => true,
+            Breakpoint::AccumulatorEquals(value) => accumulator == *value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Watchpoint {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn is_set(&self) -> bool {
+        self.read || self.write
+    }
+}
+
+// Records the access that tripped a watchpoint, and the instruction responsible
+#[derive(Clone, Copy)]
+pub struct WatchHit {
+    pub address: u8,
+    pub write: bool,
+    pub rom_bank: usize,
+    pub instruction_pointer: u8,
+}
+
+// One slot per address in the memory map
+#[derive(Clone, Copy)]
+pub struct AddressMap<T>([T; 0x100]);
+
+impl<T: Copy + Default> Default for AddressMap<T> {
+    fn default() -> Self {
+        Self([T::default(); 0x100])
+    }
+}
+
+impl<T: Copy> AddressMap<T> {
+    pub fn get(&self, address: u8) -> T {
+        self.0[address as usize]
+    }
+
+    fn set(&mut self, address: u8, value: T) {
+        self.0[address as usize] = value;
+    }
+}
+
+// One slot per ROM word, in each bank
+#[derive(Clone, Copy)]
+pub struct BreakpointMap([[Option<Breakpoint>; 0x40]; 4]);
+
+impl Default for BreakpointMap {
+    fn default() -> Self {
+        Self([[None; 0x40]; 4])
+    }
+}
+
+impl BreakpointMap {
+    pub fn get(&self, bank: usize, address: u8) -> Option<Breakpoint> {
+        self.0[bank][address as usize / 2]
+    }
+
+    fn get_mut(&mut self, bank: usize, address: u8) -> &mut Option<Breakpoint> {
+        &mut self.0[bank][address as usize / 2]
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Debugger {
+    pub breakpoints: BreakpointMap,
+
+    // One-shot breakpoint used by step over and run to cursor, as (bank, address)
+    pub temporary_breakpoint: Option<(usize, u8)>,
+
+    pub watchpoints: AddressMap<Watchpoint>,
+    pub watch_hit: Option<WatchHit>,
+
+    // The instruction that last wrote to each address, as (bank, address)
+    pub last_writers: AddressMap<Option<(usize, u8)>>,
+
+    // Set when resuming, so we don't immediately stop again on the breakpoint we're sitting on
+    resuming: bool,
+}
+
+impl Debugger {
+    pub fn toggle_breakpoint(&mut self, bank: usize, address: u8) {
+        let breakpoint = self.breakpoints.get_mut(bank, address);
+
+        *breakpoint = match breakpoint {
+            Some(_) => None,
+            None => Some(Breakpoint::Always),
+        };
+    }
+
+    pub fn set_breakpoint(&mut self, bank: usize, address: u8, breakpoint: Option<Breakpoint>) {
+        *self.breakpoints.get_mut(bank, address) = breakpoint;
+    }
+
+    pub fn set_watchpoint(&mut self, address: u8, watchpoint: Watchpoint) {
+        self.watchpoints.set(address, watchpoint);
+    }
+
+    pub fn resume(&mut self) {
+        self.resuming = true;
+        self.watch_hit = None;
+    }
+
+    // Called on every read and write the program makes (but not instruction fetches).
+    // Returns true if the CPU should pause once the current instruction finishes.
+    pub fn watch(
+        &mut self,
+        address: u8,
+        write: bool,
+        rom_bank: usize,
+        instruction_pointer: u8,
+    ) -> bool {
+        if write {
+            self.last_writers
+                .set(address, Some((rom_bank, instruction_pointer)));
+        }
+
+        let watchpoint = self.watchpoints.get(address);
+        if (write && watchpoint.write) || (!write && watchpoint.read) {
+            self.watch_hit = Some(WatchHit {
+                address,
+                write,
+                rom_bank,
+                instruction_pointer,
+            });
+            return true;
+        }
+
+        false
+    }
+
+    // Watchpoints and breakpoints are kept, but anything describing the old program state is not
+    pub fn reset(&mut self) {
+        self.temporary_breakpoint = None;
+        self.watch_hit = None;
+        self.last_writers = AddressMap::default();
+    }
+
+    // Checked before each instruction is fetched
+    pub fn should_break(&mut self, bank: usize, address: u8, accumulator: u8) -> bool {
+        if self.resuming {
+            self.resuming = false;
+            return false;
+        }
+
+        if self.temporary_breakpoint == Some((bank, address)) {
+            self.temporary_breakpoint = None;
+            return true;
+        }
+
+        // Only ROM can hold breakpoints, since that's where the editor can set them
+        if address >= 0x80 {
+            return false;
+        }
+
+        match self.breakpoints.get(bank, address) {
+            Some(breakpoint) => breakpoint.hit(accumulator),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+use crate::{
+    interrupt::InterruptController,
+    machine::{CpuFault, FaultRecord, Machine, StatusRegister},
+    sound::SoundChip,
+};
+
+// At the default interval this reaches back half a million instructions: about half a second at
+// the highest clock speed, and much longer at typical ones. Points between snapshots are reached
+// by re-executing from the one before, so a longer interval only makes seeking a little slower.
+const HISTORY_LENGTH: usize = 8192;
+const DEFAULT_INTERVAL: u32 = 64;
+
+// Everything needed to put the CPU back exactly as it was, including the sound chip
+// (but not any peripherals added to the bus, which can't be copied in general)
+#[derive(Clone)]
+pub struct MachineState {
+    // Number of instructions executed (since reset) when this was captured
+    pub instruction: u64,
+
+    accumulator: u8,
+    x_register: u8,
+    y_register: u8,
+    instruction_pointer: u8,
+    rom_bank: usize,
+    status_register: StatusRegister,
+    stack_pointer: u8,
+    cycles_waiting: u8,
+
+    fault: Option<FaultRecord>,
+    fault_vector: Option<u8>,
+    handled_fault: Option<CpuFault>,
+    fault_handler_stack: Option<u8>,
+
+    ram: [u8; 0x20],
+    stack: [u8; 0x10],
+    sound: SoundChip,
+    interrupts: InterruptController,
+}
+
+impl MachineState {
+    pub fn capture(machine: &Machine) -> Self {
+        Self {
+            instruction: machine.history.instructions_executed,
+
+            accumulator: machine.accumulator,
+            x_register: machine.x_register,
+            y_register: machine.y_register,
+            instruction_pointer: machine.instruction_pointer,
+            rom_bank: machine.rom_bank,
+            status_register: machine.status_register,
+            stack_pointer: machine.stack_pointer,
+            cycles_waiting: machine.cycles_waiting,
+
+            fault: machine.fault,
+            fault_vector: machine.fault_vector,
+            handled_fault: machine.handled_fault,
+            fault_handler_stack: machine.fault_handler_stack,
+
+            ram: machine.ram,
+            stack: machine.stack,
+            sound: machine.sound.clone(),
+            interrupts: machine.interrupts.clone(),
+        }
+    }
+
+    pub fn restore(&self, machine: &mut Machine) {
+        machine.history.instructions_executed = self.instruction;
+
+        machine.accumulator = self.accumulator;
+        machine.x_register = self.x_register;
+        machine.y_register = self.y_register;
+        machine.instruction_pointer = self.instruction_pointer;
+        machine.current_instruction_pointer = self.instruction_pointer;
+        machine.rom_bank = self.rom_bank;
+        machine.status_register = self.status_register;
+        machine.stack_pointer = self.stack_pointer;
+        machine.cycles_waiting = self.cycles_waiting;
+
+        machine.fault = self.fault;
+        machine.fault_vector = self.fault_vector;
+        machine.handled_fault = self.handled_fault;
+        machine.fault_handler_stack = self.fault_handler_stack;
+
+        machine.ram = self.ram;
+        machine.stack = self.stack;
+        machine.sound = SoundChip {
+            quality: machine.sound.quality,
+            mode: machine.sound.mode,
+            ..self.sound.clone()
+        };
+        machine.interrupts = self.interrupts.clone();
+    }
+}
+
+// Bounded history of machine states, captured every `interval` instructions.
+// Storage is allocated up front, so recording never allocates on the audio thread.
+pub struct History {
+    states: VecDeque<MachineState>,
+    pub interval: u32,
+    pub instructions_executed: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            states: VecDeque::with_capacity(HISTORY_LENGTH),
+            interval: DEFAULT_INTERVAL,
+            instructions_executed: 0,
+        }
+    }
+}
+
+impl History {
+    // u64::is_multiple_of needs Rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn due(&self) -> bool {
+        self.instructions_executed % self.interval as u64 == 0
+    }
+
+    pub fn push(&mut self, state: MachineState) {
+        // After stepping back, execution forks from the restored state, so the old future is dropped
+        while self
+            .states
+            .back()
+            .is_some_and(|last| last.instruction >= state.instruction)
+        {
+            self.states.pop_back();
+        }
+
+        if self.states.len() == HISTORY_LENGTH {
+            self.states.pop_front();
+        }
+
+        self.states.push_back(state);
+    }
+
+    // The latest state captured at or before the given instruction (or the oldest we have)
+    pub fn find(&self, instruction: u64) -> Option<&MachineState> {
+        self.states
+            .iter()
+            .rev()
+            .find(|state| state.instruction <= instruction)
+            .or_else(|| self.states.front())
+    }
+
+    // Instruction counts of the oldest and newest states
+    pub fn range(&self) -> Option<(u64, u64)> {
+        Some((
+            self.states.front()?.instruction,
+            self.states.back()?.instruction,
+        ))
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+        self.instructions_executed = 0;
+    }
+}
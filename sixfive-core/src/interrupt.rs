@@ -0,0 +1,132 @@
+// Memory-mapped registers (0xB0..=0xB4):
+// 0xB0: kind of the event being serviced (read only)
+// 0xB1: note number / controller number (read only)
+// 0xB2: velocity / controller value, 0-127 (read only)
+// 0xB3: interrupt enable mask (bit 0: note on, bit 1: note off, bit 2: control change)
+// 0xB4: address of the interrupt handler
+
+use crate::{bus::Peripheral, machine::CpuFault};
+
+const QUEUE_LENGTH: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MidiEventKind {
+    NoteOn = 1,
+    NoteOff = 2,
+    ControlChange = 3,
+}
+
+impl MidiEventKind {
+    fn mask(&self) -> u8 {
+        match self {
+            MidiEventKind::NoteOn => 0b001,
+            MidiEventKind::NoteOff => 0b010,
+            MidiEventKind::ControlChange => 0b100,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MidiEvent {
+    pub kind: MidiEventKind,
+    pub data_1: u8,
+    pub data_2: u8,
+}
+
+#[derive(Clone)]
+pub struct InterruptController {
+    pub enable_mask: u8,
+    pub vector: u8,
+
+    // Latched when the CPU starts servicing an event
+    pub kind: u8,
+    pub data_1: u8,
+    pub data_2: u8,
+
+    // Set while the handler is running, so that handlers can't be interrupted
+    pub servicing: bool,
+
+    // Fixed-size queue so that chords aren't dropped (and nothing allocates on the audio thread)
+    queue: [Option<MidiEvent>; QUEUE_LENGTH],
+    queue_start: usize,
+    queue_length: usize,
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self {
+            enable_mask: 0,
+            vector: 0,
+
+            kind: 0,
+            data_1: 0,
+            data_2: 0,
+
+            servicing: false,
+
+            queue: [None; QUEUE_LENGTH],
+            queue_start: 0,
+            queue_length: 0,
+        }
+    }
+}
+
+impl Peripheral for InterruptController {
+    fn read(&self, address: u8) -> Result<u8, CpuFault> {
+        match address {
+            0xB0 => Ok(self.kind),
+            0xB1 => Ok(self.data_1),
+            0xB2 => Ok(self.data_2),
+            0xB3 => Ok(self.enable_mask),
+            0xB4 => Ok(self.vector),
+            _ => Err(CpuFault::BusError(address)),
+        }
+    }
+
+    fn write(&mut self, address: u8, value: u8) -> Result<(), CpuFault> {
+        match address {
+            0xB0..=0xB2 => return Err(CpuFault::WriteProtect(address)),
+            0xB3 => self.enable_mask = value,
+            0xB4 => self.vector = value,
+            _ => return Err(CpuFault::BusError(address)),
+        }
+
+        Ok(())
+    }
+}
+
+impl InterruptController {
+    // Returns whether the event was queued, rather than masked or dropped because the queue is full
+    pub fn queue(&mut self, event: MidiEvent) -> bool {
+        if self.enable_mask & event.kind.mask() == 0 || self.queue_length == QUEUE_LENGTH {
+            return false;
+        }
+
+        self.queue[(self.queue_start + self.queue_length) % QUEUE_LENGTH] = Some(event);
+        self.queue_length += 1;
+        true
+    }
+
+    // Whether there's an event waiting that the CPU can service now
+    pub fn pending(&self) -> bool {
+        !self.servicing && self.queue_length > 0
+    }
+
+    // Latches the next pending event into the registers, returning the handler address
+    pub fn poll(&mut self) -> Option<u8> {
+        if !self.pending() {
+            return None;
+        }
+
+        let event = self.queue[self.queue_start].take()?;
+        self.queue_start = (self.queue_start + 1) % QUEUE_LENGTH;
+        self.queue_length -= 1;
+
+        self.kind = event.kind as u8;
+        self.data_1 = event.data_1;
+        self.data_2 = event.data_2;
+        self.servicing = true;
+
+        Some(self.vector)
+    }
+}
@@ -0,0 +1,220 @@
+// The instruction set, as the assembler and disassembler see it.
+// Each entry is the even (immediate) opcode; the odd opcode is the absolute version.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    // Argument is ignored, so it can be left off (HALT, RET, INX, ...)
+    Implied,
+    // Operand is a value: #$nn (immediate, even opcode) or $nn (read from memory, odd opcode)
+    Value,
+    // Operand is an address: $nn (even opcode) or ($nn) (address read from memory, odd opcode)
+    Address,
+    // AWI0 ... AWIF: #$nn uses 0xA0..=0xAF, $nn uses 0xB0..=0xBF
+    Sound,
+}
+
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub opcode: u8,
+    pub operand: OperandKind,
+}
+
+const fn instruction(mnemonic: &'static str, opcode: u8, operand: OperandKind) -> Instruction {
+    Instruction {
+        mnemonic,
+        opcode,
+        operand,
+    }
+}
+
+use OperandKind::*;
+
+pub const INSTRUCTIONS: &[Instruction] = &[
+    instruction("HALT", 0x00, Implied),
+    instruction("LOAD", 0x10, Value),
+    instruction("STOR", 0x12, Address),
+    instruction("ADD", 0x20, Value),
+    instruction("SSR", 0x22, Value),
+    instruction("SUB", 0x24, Value),
+    instruction("CMP", 0x26, Value),
+    instruction("ADC", 0x28, Value),
+    instruction("SBC", 0x2A, Value),
+    instruction("SEC", 0x2C, Implied),
+    instruction("CLC", 0x2E, Implied),
+    instruction("BREQ", 0x30, Address),
+    instruction("BRNE", 0x32, Address),
+    instruction("BRLT", 0x34, Address),
+    instruction("BRGE", 0x36, Address),
+    instruction("BRCS", 0x38, Address),
+    instruction("BRCC", 0x3A, Address),
+    instruction("BRVS", 0x3C, Address),
+    instruction("BRVC", 0x3E, Address),
+    instruction("JMP", 0x40, Address),
+    instruction("CALL", 0x42, Address),
+    instruction("RET", 0x44, Implied),
+    instruction("RETI", 0x46, Implied),
+    instruction("FJMP", 0x48, Address),
+    instruction("FCAL", 0x4A, Address),
+    instruction("FRET", 0x4C, Implied),
+    instruction("AND", 0x50, Value),
+    instruction("BIT", 0x52, Value),
+    instruction("OR", 0x54, Value),
+    instruction("XOR", 0x56, Value),
+    instruction("LSL", 0x58, Value),
+    instruction("LSR", 0x5A, Value),
+    instruction("ROL", 0x5C, Value),
+    instruction("ROR", 0x5E, Value),
+    instruction("ZERO", 0x60, Address),
+    instruction("INC", 0x62, Address),
+    instruction("DEC", 0x64, Address),
+    instruction("PUSH", 0x70, Implied),
+    instruction("PULL", 0x72, Implied),
+    instruction("PUSF", 0x74, Implied),
+    instruction("PULF", 0x76, Implied),
+    instruction("LDX", 0x80, Value),
+    instruction("LDY", 0x82, Value),
+    instruction("STX", 0x84, Address),
+    instruction("STY", 0x86, Address),
+    instruction("INX", 0x88, Implied),
+    instruction("INY", 0x8A, Implied),
+    instruction("DEX", 0x8C, Implied),
+    instruction("DEY", 0x8E, Implied),
+    instruction("TAX", 0x90, Implied),
+    instruction("TAY", 0x92, Implied),
+    instruction("TXA", 0x94, Implied),
+    instruction("TYA", 0x96, Implied),
+    instruction("CPX", 0x98, Value),
+    instruction("CPY", 0x9A, Value),
+    instruction("AWI0", 0xA0, Sound),
+    instruction("AWI1", 0xA1, Sound),
+    instruction("AWI2", 0xA2, Sound),
+    instruction("AWI3", 0xA3, Sound),
+    instruction("AWI4", 0xA4, Sound),
+    instruction("AWI5", 0xA5, Sound),
+    instruction("AWI6", 0xA6, Sound),
+    instruction("AWI7", 0xA7, Sound),
+    instruction("AWI8", 0xA8, Sound),
+    instruction("AWI9", 0xA9, Sound),
+    instruction("AWIA", 0xAA, Sound),
+    instruction("AWIB", 0xAB, Sound),
+    instruction("AWIC", 0xAC, Sound),
+    instruction("AWID", 0xAD, Sound),
+    instruction("AWIE", 0xAE, Sound),
+    instruction("AWIF", 0xAF, Sound),
+    instruction("BEAT", 0xF0, Value),
+    instruction("NOOP", 0xF2, Value),
+];
+
+// Operations available with the indexed addressing modes (0xC0..=0xEF), by (opcode & 0x0F) >> 1
+pub const INDEXED_OPERATIONS: [u8; 8] = [
+    0x10, // LOAD
+    0x12, // STOR
+    0x20, // ADD
+    0x24, // SUB
+    0x26, // CMP
+    0x28, // ADC
+    0x2A, // SBC
+    0x40, // JMP
+];
+
+// Base opcodes for each indexed addressing mode
+pub const INDEXED_BY_X: u8 = 0xC0;
+pub const INDEXED_BY_Y: u8 = 0xD0;
+pub const INDIRECT_INDEXED_BY_Y: u8 = 0xE0;
+
+pub fn find_mnemonic(mnemonic: &str) -> Option<&'static Instruction> {
+    let mnemonic = mnemonic.to_uppercase();
+
+    // JUMP is how JMP is written in the original patches
+    let mnemonic = match mnemonic.as_str() {
+        "JUMP" => "JMP",
+        other => other,
+    };
+
+    INSTRUCTIONS
+        .iter()
+        .find(|instruction| instruction.mnemonic == mnemonic)
+}
+
+// The instruction for an opcode, ignoring the addressing mode bit
+pub fn find_opcode(opcode: u8) -> Option<&'static Instruction> {
+    INSTRUCTIONS
+        .iter()
+        .find(|instruction| match instruction.operand {
+            Sound => opcode & 0x0F == instruction.opcode & 0x0F && (0xA0..=0xBF).contains(&opcode),
+            _ => opcode & !0b1 == instruction.opcode,
+        })
+}
+
+// Instructions that write to their (even opcode, direct) address operand
+const WRITES_ADDRESS: [u8; 6] = [0x12, 0x60, 0x62, 0x64, 0x84, 0x86];
+
+// Formats an operand the same way the assembler reads it
+fn format_operand(kind: OperandKind, opcode: u8, argument: u8) -> String {
+    let absolute = match kind {
+        Sound => opcode >= 0xB0,
+        _ => opcode & 0b1 == 1,
+    };
+
+    match kind {
+        Implied if !absolute && argument == 0 => String::new(),
+        Implied | Value | Sound if absolute => format!("${:02X}", argument),
+        Implied | Value | Sound => format!("#${:02X}", argument),
+        Address if absolute => format!("(${:02X})", argument),
+        Address => format!("${:02X}", argument),
+    }
+}
+
+// Decodes an instruction word (as stored in ROM), or None if the opcode is illegal
+pub fn disassemble(word: u16) -> Option<String> {
+    let opcode = (word >> 8) as u8;
+    let argument = word as u8;
+
+    let (instruction, operand) = match opcode & 0xF0 {
+        INDEXED_BY_X | INDEXED_BY_Y | INDIRECT_INDEXED_BY_Y => {
+            let instruction = find_opcode(INDEXED_OPERATIONS[(opcode as usize & 0x0F) >> 1])?;
+
+            // Index the address, then wrap it in whatever the operand kind would normally use
+            let address = match opcode & 0xF0 {
+                INDEXED_BY_X => format!("${:02X},X", argument),
+                INDEXED_BY_Y => format!("${:02X},Y", argument),
+                _ => format!("(${:02X}),Y", argument),
+            };
+
+            let operand = match (instruction.operand, opcode & 0b1 == 1) {
+                (Address, true) => format!("({})", address),
+                (Address, false) => address,
+                (_, true) => address,
+                (_, false) => format!("#{}", address),
+            };
+
+            (instruction, operand)
+        }
+        _ => {
+            let instruction = find_opcode(opcode)?;
+            (
+                instruction,
+                format_operand(instruction.operand, opcode, argument),
+            )
+        }
+    };
+
+    if operand.is_empty() {
+        Some(instruction.mnemonic.to_string())
+    } else {
+        Some(format!("{} {}", instruction.mnemonic, operand))
+    }
+}
+
+// The address an instruction word writes to, if it can be known without running it
+pub fn written_address(word: u16) -> Option<u8> {
+    let opcode = (word >> 8) as u8;
+    let argument = word as u8;
+
+    match opcode {
+        // AWI0 ... AWIF
+        0xA0..=0xBF => Some(0xA0 | (opcode & 0x0F)),
+        _ if WRITES_ADDRESS.contains(&opcode) => Some(argument),
+        _ => None,
+    }
+}
@@ -0,0 +1,18 @@
+// The emulated machine, independent of any plugin framework: the CPU, its memory map and
+// the sound chip. The plugin (and any other host) drives it through Machine.
+
+pub mod apu;
+pub mod assembler;
+pub mod bus;
+pub mod debug;
+pub mod history;
+pub mod interrupt;
+pub mod isa;
+pub mod machine;
+pub mod rom;
+pub mod scheduler;
+pub mod sound;
+pub mod trace;
+
+pub use machine::Machine;
+pub use rom::Rom;
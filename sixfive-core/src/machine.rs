@@ -0,0 +1,1429 @@
+use std::sync::Arc;
+
+use crate::{
+    bus::{Bus, Device, Peripheral},
+    debug::Debugger,
+    history::{History, MachineState},
+    interrupt::{InterruptController, MidiEvent},
+    isa::INDEXED_OPERATIONS,
+    rom::Rom,
+    sound::SoundChip,
+    trace::{TraceBuffer, TraceEvent},
+};
+
+// The stack lives at 0xE0..=0xEF and grows downwards, wrapping within that region
+const STACK_BOTTOM: u8 = 0xE0;
+const STACK_TOP: u8 = 0xEF;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CpuFault {
+    // Access to an address with nothing mapped to it
+    BusError(u8),
+    // Opcode that doesn't correspond to any instruction
+    IllegalOpcode(u8),
+    // Write to a read-only address (ROM, trampoline vectors, status registers)
+    WriteProtect(u8),
+    // Push with all 16 bytes of the stack in use
+    StackOverflow(u8),
+    // Pull with nothing on the stack
+    StackUnderflow(u8),
+}
+
+impl CpuFault {
+    // Readable by fault handlers at 0xBA
+    pub fn code(&self) -> u8 {
+        match self {
+            CpuFault::BusError(_) => 1,
+            CpuFault::IllegalOpcode(_) => 2,
+            CpuFault::WriteProtect(_) => 3,
+            CpuFault::StackOverflow(_) => 4,
+            CpuFault::StackUnderflow(_) => 5,
+        }
+    }
+
+    // Readable by fault handlers at 0xBB (the opcode, for illegal opcodes,
+    // and the stack pointer, for stack faults)
+    pub fn address(&self) -> u8 {
+        match self {
+            CpuFault::BusError(address) => *address,
+            CpuFault::IllegalOpcode(opcode) => *opcode,
+            CpuFault::WriteProtect(address) => *address,
+            CpuFault::StackOverflow(stack_pointer) => *stack_pointer,
+            CpuFault::StackUnderflow(stack_pointer) => *stack_pointer,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            CpuFault::BusError(address) => format!("Bus error at 0x{:02X}", address),
+            CpuFault::IllegalOpcode(opcode) => format!("Illegal opcode 0x{:02X}", opcode),
+            CpuFault::WriteProtect(address) => format!("Write to read-only 0x{:02X}", address),
+            CpuFault::StackOverflow(_) => "Stack overflow".to_string(),
+            CpuFault::StackUnderflow(_) => "Stack underflow".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct FaultRecord {
+    pub fault: CpuFault,
+    pub instruction_pointer: u8,
+    pub rom_bank: usize,
+    pub instruction: u16,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct StatusRegister {
+    pub carry: bool,
+    pub overflow: bool,
+    pub zero: bool,
+    pub negative: bool,
+}
+
+impl StatusRegister {
+    // Laid out like the 6502: NV-- --ZC
+    pub fn to_byte(self) -> u8 {
+        let mut value = 0;
+        value |= (self.negative as u8) << 7;
+        value |= (self.overflow as u8) << 6;
+        value |= (self.zero as u8) << 1;
+        value |= self.carry as u8;
+        value
+    }
+
+    pub fn from_byte(value: u8) -> Self {
+        Self {
+            carry: value & 0b0000_0001 != 0,
+            overflow: value & 0b0100_0000 != 0,
+            zero: value & 0b0000_0010 != 0,
+            negative: value & 0b1000_0000 != 0,
+        }
+    }
+}
+
+pub struct Machine {
+    pub accumulator: u8,
+    pub x_register: u8,
+    pub y_register: u8,
+    pub instruction_pointer: u8,
+    // Address of the instruction being executed (instruction_pointer moves on before it finishes)
+    pub current_instruction_pointer: u8,
+    pub rom_bank: usize,
+    pub status_register: StatusRegister,
+    pub stack_pointer: u8,
+
+    pub clock_running: bool,
+    // Stopped by HALT (rather than the debugger), so an interrupt can start the clock again
+    pub halted: bool,
+    pub beat_position: f64,
+    pub beat_target: Option<f64>,
+    pub cycles_waiting: u8,
+
+    // Set when a fault halts the CPU
+    pub fault: Option<FaultRecord>,
+    pub fault_vector: Option<u8>,
+    // The fault the handler was called for (readable at 0xBA and 0xBB), and the stack pointer
+    // to expect once it has returned. A fault raised before then halts instead of nesting.
+    pub handled_fault: Option<CpuFault>,
+    pub fault_handler_stack: Option<u8>,
+
+    pub ram: [u8; 0x20],
+    pub stack: [u8; 0x10],
+    pub sound: SoundChip,
+    pub interrupts: InterruptController,
+    pub rom: Rom,
+    pub bus: Bus,
+
+    pub debugger: Debugger,
+    pub history: History,
+    pub trace: Arc<TraceBuffer>,
+    // Advanced by the host every sample, to timestamp trace entries
+    pub timestamp: u64,
+    // Set while re-executing instructions to reach a point between history snapshots,
+    // which shouldn't be traced, recorded again or stopped by the debugger
+    replaying: bool,
+}
+
+impl Machine {
+    pub fn new(rom: Rom) -> Self {
+        Self {
+            accumulator: 0,
+            x_register: 0,
+            y_register: 0,
+            instruction_pointer: 0,
+            current_instruction_pointer: 0,
+            rom_bank: rom.power_on_bank,
+            status_register: StatusRegister::default(),
+            stack_pointer: STACK_TOP,
+
+            clock_running: false,
+            halted: false,
+            beat_position: 0.0,
+            beat_target: None,
+            cycles_waiting: 0,
+
+            fault: None,
+            fault_vector: None,
+            handled_fault: None,
+            fault_handler_stack: None,
+
+            ram: [0; 0x20],
+            stack: [0; 0x10],
+            sound: SoundChip::default(),
+            interrupts: InterruptController::default(),
+
+            rom,
+            bus: Bus::default(),
+
+            debugger: Debugger::default(),
+            history: History::default(),
+            trace: Arc::new(TraceBuffer::default()),
+            timestamp: 0,
+            replaying: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.instruction_pointer = 0;
+        self.rom_bank = self.rom.power_on_bank;
+        self.accumulator = 0;
+        self.x_register = 0;
+        self.y_register = 0;
+        self.status_register = StatusRegister::default();
+        self.stack_pointer = STACK_TOP;
+        self.beat_target = None;
+        self.halted = false;
+        self.fault = None;
+        self.fault_vector = None;
+        self.handled_fault = None;
+        self.fault_handler_stack = None;
+        self.ram = [0; 0x20];
+        self.stack = [0; 0x10];
+        // Quality and mode are settings from the host, not machine state
+        self.sound = SoundChip {
+            quality: self.sound.quality,
+            mode: self.sound.mode,
+            ..SoundChip::default()
+        };
+        self.interrupts = InterruptController::default();
+        self.debugger.reset();
+        self.history.clear();
+    }
+
+    // Only updates the zero and negative flags, carry and overflow are left alone
+    fn set_status_register(&mut self, value: u8) {
+        self.status_register.zero = value == 0;
+        self.status_register.negative = value & 0b1000_0000 != 0;
+    }
+
+    fn add_with_carry(&mut self, operand: u8, carry: bool) -> u8 {
+        let sum = self.accumulator as u16 + operand as u16 + carry as u16;
+        let result = sum as u8;
+
+        self.status_register.carry = sum > 0xFF;
+        // Signed overflow: both inputs share a sign that differs from the result
+        self.status_register.overflow =
+            (self.accumulator ^ result) & (operand ^ result) & 0b1000_0000 != 0;
+        self.set_status_register(result);
+
+        result
+    }
+
+    // Data reads go through here, so that they can trip watchpoints
+    fn read(&mut self, address: u8) -> Result<u8, CpuFault> {
+        if !self.replaying
+            && self.debugger.watch(
+                address,
+                false,
+                self.rom_bank,
+                self.current_instruction_pointer,
+            )
+        {
+            self.clock_running = false;
+        }
+
+        self.fetch(address)
+    }
+
+    // Instruction fetches (and the debugger) read memory directly
+    fn fetch(&self, address: u8) -> Result<u8, CpuFault> {
+        let (device, device_address) = self.bus.resolve(address);
+        match device {
+            Device::Unmapped => Err(CpuFault::BusError(address)),
+            Device::Rom => Ok(self.rom.read_rom(self.rom_bank, device_address)),
+            Device::TrampolineVectors => Ok(self.rom.read_trampoline_vector(device_address)),
+            Device::System => self.read_system_register(device_address),
+            Device::Ram => self.ram.read(device_address),
+            Device::Sound => self.sound.read(device_address),
+            Device::Interrupts => self.interrupts.read(device_address),
+            Device::Stack => self.stack.read(device_address),
+            Device::Custom(index) => self.bus.peripheral(index).read(device_address),
+        }
+    }
+
+    fn write(&mut self, address: u8, value: u8) -> Result<(), CpuFault> {
+        // Writing the bank register switches banks, but the writer is still in the old one
+        let rom_bank = self.rom_bank;
+
+        let (device, device_address) = self.bus.resolve(address);
+        match device {
+            Device::Unmapped => Err(CpuFault::BusError(address)),
+            Device::Rom | Device::TrampolineVectors => Err(CpuFault::WriteProtect(address)),
+            Device::System => self.write_system_register(device_address, value),
+            Device::Ram => self.ram.write(device_address, value),
+            Device::Sound => self.sound.write(device_address, value),
+            Device::Interrupts => self.interrupts.write(device_address, value),
+            Device::Stack => self.stack.write(device_address, value),
+            Device::Custom(index) => self.bus.peripheral_mut(index).write(device_address, value),
+        }?;
+
+        // Only once the write has landed, so that faulting writes aren't shown as happening
+        if !self.replaying {
+            self.trace
+                .log(self.timestamp, TraceEvent::Write { address, value });
+        }
+
+        if self
+            .debugger
+            .watch(address, true, rom_bank, self.current_instruction_pointer)
+        {
+            self.clock_running = false;
+        }
+
+        Ok(())
+    }
+
+    fn read_system_register(&self, address: u8) -> Result<u8, CpuFault> {
+        match address {
+            0xB8 => Ok(self.rom_bank as u8),
+            0xB9 => Ok(self.fault_vector.unwrap_or(0)),
+            0xBA => Ok(self.handled_fault.map_or(0, |fault| fault.code())),
+            0xBB => Ok(self.handled_fault.map_or(0, |fault| fault.address())),
+            _ => Err(CpuFault::BusError(address)),
+        }
+    }
+
+    fn write_system_register(&mut self, address: u8, value: u8) -> Result<(), CpuFault> {
+        match address {
+            0xB8 => self.rom_bank = (value & 0b11) as usize,
+            0xB9 => self.fault_vector = Some(value),
+            0xBA..=0xBB => return Err(CpuFault::WriteProtect(address)),
+            _ => return Err(CpuFault::BusError(address)),
+        }
+
+        Ok(())
+    }
+
+    // The stack pointer points at the next free byte, which is just below the stack once it's full
+    fn push(&mut self, value: u8) -> Result<(), CpuFault> {
+        if self.stack_pointer < STACK_BOTTOM {
+            return Err(CpuFault::StackOverflow(self.stack_pointer));
+        }
+
+        self.write(self.stack_pointer, value)?;
+        self.stack_pointer -= 1;
+        Ok(())
+    }
+
+    fn pull(&mut self) -> Result<u8, CpuFault> {
+        if self.stack_pointer >= STACK_TOP {
+            return Err(CpuFault::StackUnderflow(self.stack_pointer));
+        }
+
+        self.stack_pointer += 1;
+
+        // Pulling the fault handler's return address means it's finished
+        if self.fault_handler_stack == Some(self.stack_pointer) {
+            self.fault_handler_stack = None;
+        }
+
+        self.read(self.stack_pointer)
+    }
+
+    // Called by the host with the transport position (in quarter notes) for each sample
+    pub fn set_beat_position(&mut self, position: f64) {
+        // If the transport jumped backwards (looping, or the user moved the playhead),
+        // the beat we were waiting on may never come, so stop waiting
+        // (with some tolerance, since hosts and our own counting won't agree exactly)
+        if position < self.beat_position - 1e-3 {
+            self.beat_target = None;
+        }
+
+        self.beat_position = position;
+    }
+
+    pub fn waiting_for_beat(&self) -> bool {
+        // A pending interrupt cuts the wait short
+        if self.interrupts.pending() {
+            return false;
+        }
+
+        match self.beat_target {
+            Some(target) => self.beat_position < target,
+            None => false,
+        }
+    }
+
+    // Called by the host for each incoming MIDI event
+    pub fn queue_interrupt(&mut self, event: MidiEvent) {
+        if !self.interrupts.queue(event) {
+            return;
+        }
+
+        // Snapshots taken before the event don't have it queued, so replaying from them would
+        // never service it. This one does, so seeking past this point replays the interrupt.
+        let state = MachineState::capture(self);
+        self.history.push(state);
+
+        if self.halted && self.interrupts.pending() {
+            self.halted = false;
+            self.clock_running = true;
+        }
+    }
+
+    pub fn execute(&mut self) {
+        self.bus.tick();
+
+        // Interrupts are serviced straight away, rather than after a NOOP or BEAT finishes waiting
+        if self.interrupts.pending() {
+            self.beat_target = None;
+            self.cycles_waiting = 0;
+        }
+
+        if self.waiting_for_beat() {
+            return;
+        }
+        self.beat_target = None;
+
+        if self.cycles_waiting > 0 {
+            self.cycles_waiting -= 1;
+            return;
+        }
+
+        if let Err(fault) = self.step() {
+            self.raise_fault(fault);
+        }
+    }
+
+    pub fn run(&mut self) {
+        // A faulted CPU has to have the fault cleared (or be reset) before it runs again
+        if self.fault.is_none() {
+            self.debugger.resume();
+            self.clock_running = true;
+            self.halted = false;
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.clock_running = false;
+        self.halted = false;
+    }
+
+    pub fn run_to(&mut self, bank: usize, address: u8) {
+        self.debugger.temporary_breakpoint = Some((bank, address));
+        self.run();
+    }
+
+    // Executes exactly one instruction, skipping over any wait in progress
+    pub fn step_instruction(&mut self) {
+        if self.fault.is_some() {
+            return;
+        }
+
+        self.cycles_waiting = 0;
+        self.beat_target = None;
+        self.halted = false;
+        self.debugger.resume();
+        self.execute();
+    }
+
+    // Like step_instruction, but runs subroutine calls until they return
+    pub fn step_over(&mut self) {
+        match self.fetch(self.instruction_pointer) {
+            // CALL, FCAL
+            Ok(0x42 | 0x43 | 0x4A | 0x4B) => {
+                self.run_to(self.rom_bank, self.instruction_pointer.wrapping_add(2))
+            }
+            _ => self.step_instruction(),
+        }
+    }
+
+    // Goes back to just before the last instruction executed
+    pub fn step_back(&mut self) {
+        if let Some(instruction) = self.history.instructions_executed.checked_sub(1) {
+            self.seek(instruction);
+        }
+    }
+
+    // Goes back (or forward) to the given instruction count: restores the latest recorded state
+    // at or before it, then re-executes up to it. A state is recorded whenever an interrupt is
+    // queued, so the ones that arrived in between are replayed too.
+    pub fn seek(&mut self, instruction: u64) {
+        if let Some(state) = self.history.find(instruction).cloned() {
+            state.restore(self);
+
+            self.replaying = true;
+            while self.history.instructions_executed < instruction && self.fault.is_none() {
+                let executed = self.history.instructions_executed;
+                if let Err(fault) = self.step() {
+                    self.raise_fault(fault);
+                }
+
+                if self.history.instructions_executed == executed {
+                    break;
+                }
+            }
+            self.replaying = false;
+
+            self.cycles_waiting = 0;
+            self.clock_running = false;
+            self.beat_target = None;
+            self.debugger.watch_hit = None;
+        }
+    }
+
+    fn raise_fault(&mut self, fault: CpuFault) {
+        let instruction_pointer = self.current_instruction_pointer;
+        let rom_bank = self.rom_bank;
+        let opcode = self.fetch(instruction_pointer).unwrap_or(0);
+        let argument = self.fetch(instruction_pointer.wrapping_add(1)).unwrap_or(0);
+
+        // With a fault handler installed, call it like a subroutine so that it can
+        // RET to the instruction after the one that faulted
+        if let (Some(vector), None) = (self.fault_vector, self.fault_handler_stack) {
+            let stack_pointer = self.stack_pointer;
+            let return_address = instruction_pointer.wrapping_add(2);
+            if self.push(return_address).is_ok() {
+                self.handled_fault = Some(fault);
+                self.fault_handler_stack = Some(stack_pointer);
+                self.instruction_pointer = vector;
+                return;
+            }
+        }
+
+        // Otherwise (or if the handler faults too), halt on the faulting instruction
+        // so it can be inspected
+        self.fault = Some(FaultRecord {
+            fault,
+            instruction_pointer,
+            rom_bank,
+            instruction: ((opcode as u16) << 8) | argument as u16,
+        });
+        self.instruction_pointer = instruction_pointer;
+        self.clock_running = false;
+    }
+
+    fn step(&mut self) -> Result<(), CpuFault> {
+        if let Some(vector) = self.interrupts.poll() {
+            // The handler might switch banks, so the bank is saved like FCAL does
+            self.push(self.instruction_pointer)?;
+            self.push(self.rom_bank as u8)?;
+            self.push(self.status_register.to_byte())?;
+            self.instruction_pointer = vector;
+        }
+
+        if !self.replaying
+            && self
+                .debugger
+                .should_break(self.rom_bank, self.instruction_pointer, self.accumulator)
+        {
+            self.clock_running = false;
+            return Ok(());
+        }
+
+        if !self.replaying && self.history.due() {
+            let state = MachineState::capture(self);
+            self.history.push(state);
+        }
+        self.history.instructions_executed += 1;
+
+        self.current_instruction_pointer = self.instruction_pointer;
+
+        let opcode = self.fetch(self.instruction_pointer)?;
+
+        // The last bit of the opcode determines the addressing mode:
+        // 0: immediate
+        // 1: absolute
+
+        // With the exception of 0xA0..=0xAF, which are always immediate
+        // And 0xB0..=0xBF, which are always absolute
+        // (so that the last 4 bits of the instruction match the last 4 bits of the address being written)
+
+        // 0xC0..=0xEF are indexed versions of the operations in INDEXED_OPERATIONS,
+        // where the last bit still chooses between using the address or the value stored there:
+        // 0xC0..=0xCF: indexed by X (argument + X)
+        // 0xD0..=0xDF: indexed by Y (argument + Y)
+        // 0xE0..=0xEF: indirect indexed by Y (value at argument + Y)
+        let argument = self.fetch(self.instruction_pointer.wrapping_add(1))?;
+        let (operation, operand) = match opcode {
+            0xA0..=0xAF => (opcode, argument),
+            0xB0..=0xBF => (opcode, self.read(argument)?),
+            0xC0..=0xEF => {
+                let address = match opcode & 0xF0 {
+                    0xC0 => argument.wrapping_add(self.x_register),
+                    0xD0 => argument.wrapping_add(self.y_register),
+                    _ => self.read(argument)?.wrapping_add(self.y_register),
+                };
+
+                let operation = INDEXED_OPERATIONS[(opcode as usize & 0x0F) >> 1];
+
+                if opcode & 0b0000_0001 == 0 {
+                    (operation, address)
+                } else {
+                    (operation, self.read(address)?)
+                }
+            }
+            _ => {
+                if opcode & 0b0000_0001 == 0 {
+                    (opcode, argument)
+                } else {
+                    (opcode, self.read(argument)?)
+                }
+            }
+        };
+
+        if !self.replaying {
+            self.trace.log(
+                self.timestamp,
+                TraceEvent::Instruction {
+                    instruction_pointer: self.instruction_pointer,
+                    rom_bank: self.rom_bank,
+                    opcode,
+                    operand,
+                    accumulator: self.accumulator,
+                    status_register: self.status_register.to_byte(),
+                },
+            );
+        }
+
+        // we update this now in order to avoid messing up jumps
+        self.instruction_pointer = self.instruction_pointer.wrapping_add(2);
+
+        match operation {
+            // Halt
+            0x00 | 0x01 => {
+                // HALT
+                // (enabled interrupts start the clock again, so a program can HALT to wait for notes)
+                self.clock_running = false;
+                self.halted = true;
+            }
+
+            // Loading and Storing
+            0x10 | 0x11 => {
+                // LOAD
+                self.accumulator = operand;
+                self.set_status_register(self.accumulator);
+            }
+            0x12 | 0x13 => {
+                // STOR
+                self.write(operand, self.accumulator)?;
+                self.set_status_register(self.accumulator);
+            }
+
+            // Arithmetic
+
+            // Add
+            0x20 | 0x21 => {
+                // ADD
+                self.accumulator = self.add_with_carry(operand, false);
+            }
+
+            // Set Status Register With Value
+            0x22 | 0x23 => {
+                // SSR
+                self.set_status_register(operand);
+            }
+
+            // Subtraction
+            // (the carry flag is set when no borrow occurred, like the 6502)
+            0x24 | 0x25 => {
+                // SUB
+                self.accumulator = self.add_with_carry(!operand, true);
+            }
+
+            // Comparison
+            0x26 | 0x27 => {
+                // CMP
+                let value = self.accumulator.wrapping_sub(operand);
+                self.status_register.carry = self.accumulator >= operand;
+                self.set_status_register(value);
+            }
+
+            // Add and subtract using the carry flag, for multi-byte arithmetic
+            0x28 | 0x29 => {
+                // ADC
+                self.accumulator = self.add_with_carry(operand, self.status_register.carry);
+            }
+            0x2A | 0x2B => {
+                // SBC
+                self.accumulator = self.add_with_carry(!operand, self.status_register.carry);
+            }
+
+            // Carry flag manipulation
+            0x2C | 0x2D => {
+                // SEC
+                self.status_register.carry = true;
+            }
+            0x2E | 0x2F => {
+                // CLC
+                self.status_register.carry = false;
+            }
+
+            // Branching
+            0x30 | 0x31 => {
+                // BREQ
+                if self.status_register.zero {
+                    self.instruction_pointer = operand;
+                }
+            }
+            0x32 | 0x33 => {
+                // BRNE
+                if !self.status_register.zero {
+                    self.instruction_pointer = operand;
+                }
+            }
+            0x34 | 0x35 => {
+                // BRLT
+                if self.status_register.negative {
+                    self.instruction_pointer = operand;
+                }
+            }
+            0x36 | 0x37 => {
+                // BRGE
+                if !self.status_register.negative {
+                    self.instruction_pointer = operand;
+                }
+            }
+            0x38 | 0x39 => {
+                // BRCS
+                if self.status_register.carry {
+                    self.instruction_pointer = operand;
+                }
+            }
+            0x3A | 0x3B => {
+                // BRCC
+                if !self.status_register.carry {
+                    self.instruction_pointer = operand;
+                }
+            }
+            0x3C | 0x3D => {
+                // BRVS
+                if self.status_register.overflow {
+                    self.instruction_pointer = operand;
+                }
+            }
+            0x3E | 0x3F => {
+                // BRVC
+                if !self.status_register.overflow {
+                    self.instruction_pointer = operand;
+                }
+            }
+
+            // Jumping
+            0x40 | 0x41 => {
+                // JMP
+                self.instruction_pointer = operand;
+            }
+
+            // Subroutines
+            0x42 | 0x43 => {
+                // CALL
+                self.push(self.instruction_pointer)?;
+                self.instruction_pointer = operand;
+            }
+            0x44 | 0x45 => {
+                // RET
+                self.instruction_pointer = self.pull()?;
+            }
+            0x46 | 0x47 => {
+                // RETI
+                let value = self.pull()?;
+                self.status_register = StatusRegister::from_byte(value);
+                self.rom_bank = (self.pull()? & 0b11) as usize;
+                self.instruction_pointer = self.pull()?;
+                self.interrupts.servicing = false;
+            }
+
+            // Jumping between ROM banks (the accumulator holds the bank to switch to)
+            0x48 | 0x49 => {
+                // FJMP
+                self.rom_bank = (self.accumulator & 0b11) as usize;
+                self.instruction_pointer = operand;
+            }
+            0x4A | 0x4B => {
+                // FCAL
+                self.push(self.instruction_pointer)?;
+                self.push(self.rom_bank as u8)?;
+                self.rom_bank = (self.accumulator & 0b11) as usize;
+                self.instruction_pointer = operand;
+            }
+            0x4C | 0x4D => {
+                // FRET
+                self.rom_bank = (self.pull()? & 0b11) as usize;
+                self.instruction_pointer = self.pull()?;
+            }
+
+            // Bitwise
+            0x50 | 0x51 => {
+                // AND
+                self.accumulator = self.accumulator & operand;
+                self.set_status_register(self.accumulator);
+            }
+            0x52 | 0x53 => {
+                // BIT
+                let value = self.accumulator & operand;
+                self.set_status_register(value);
+            }
+            0x54 | 0x55 => {
+                // OR
+                self.accumulator = self.accumulator | operand;
+                self.set_status_register(self.accumulator);
+            }
+            0x56 | 0x57 => {
+                // XOR
+                self.accumulator = self.accumulator ^ operand;
+                self.set_status_register(self.accumulator);
+            }
+            0x58 | 0x59 => {
+                // LSL
+                self.accumulator = self.accumulator.checked_shl(operand as u32).unwrap_or(0);
+                self.set_status_register(self.accumulator);
+            }
+            0x5A | 0x5B => {
+                // LSR
+                self.accumulator = self.accumulator.checked_shr(operand as u32).unwrap_or(0);
+                self.set_status_register(self.accumulator);
+            }
+            0x5C | 0x5D => {
+                // ROL
+                self.accumulator = self.accumulator.rotate_left(operand as u32);
+                self.set_status_register(self.accumulator);
+            }
+            0x5E | 0x5F => {
+                // ROR
+                self.accumulator = self.accumulator.rotate_right(operand as u32);
+                self.set_status_register(self.accumulator);
+            }
+
+            // Operations directly on memory
+            0x60 | 0x61 => {
+                // ZERO
+                self.write(operand, 0)?;
+                self.set_status_register(0);
+            }
+            0x62 | 0x63 => {
+                // INC
+                let value = self.read(operand)?.wrapping_add(1);
+                self.write(operand, value)?;
+                self.set_status_register(value);
+            }
+            0x64 | 0x65 => {
+                // DEC
+                let value = self.read(operand)?.wrapping_sub(1);
+                self.write(operand, value)?;
+                self.set_status_register(value);
+            }
+
+            // Stack
+            0x70 | 0x71 => {
+                // PUSH
+                self.push(self.accumulator)?;
+            }
+            0x72 | 0x73 => {
+                // PULL
+                self.accumulator = self.pull()?;
+                self.set_status_register(self.accumulator);
+            }
+            0x74 | 0x75 => {
+                // PUSF
+                self.push(self.status_register.to_byte())?;
+            }
+            0x76 | 0x77 => {
+                // PULF
+                let value = self.pull()?;
+                self.status_register = StatusRegister::from_byte(value);
+            }
+
+            // Index Registers
+            0x80 | 0x81 => {
+                // LDX
+                self.x_register = operand;
+                self.set_status_register(self.x_register);
+            }
+            0x82 | 0x83 => {
+                // LDY
+                self.y_register = operand;
+                self.set_status_register(self.y_register);
+            }
+            0x84 | 0x85 => {
+                // STX
+                self.write(operand, self.x_register)?;
+            }
+            0x86 | 0x87 => {
+                // STY
+                self.write(operand, self.y_register)?;
+            }
+            0x88 | 0x89 => {
+                // INX
+                self.x_register = self.x_register.wrapping_add(1);
+                self.set_status_register(self.x_register);
+            }
+            0x8A | 0x8B => {
+                // INY
+                self.y_register = self.y_register.wrapping_add(1);
+                self.set_status_register(self.y_register);
+            }
+            0x8C | 0x8D => {
+                // DEX
+                self.x_register = self.x_register.wrapping_sub(1);
+                self.set_status_register(self.x_register);
+            }
+            0x8E | 0x8F => {
+                // DEY
+                self.y_register = self.y_register.wrapping_sub(1);
+                self.set_status_register(self.y_register);
+            }
+            0x90 | 0x91 => {
+                // TAX
+                self.x_register = self.accumulator;
+                self.set_status_register(self.x_register);
+            }
+            0x92 | 0x93 => {
+                // TAY
+                self.y_register = self.accumulator;
+                self.set_status_register(self.y_register);
+            }
+            0x94 | 0x95 => {
+                // TXA
+                self.accumulator = self.x_register;
+                self.set_status_register(self.accumulator);
+            }
+            0x96 | 0x97 => {
+                // TYA
+                self.accumulator = self.y_register;
+                self.set_status_register(self.accumulator);
+            }
+            0x98 | 0x99 => {
+                // CPX
+                let value = self.x_register.wrapping_sub(operand);
+                self.status_register.carry = self.x_register >= operand;
+                self.set_status_register(value);
+            }
+            0x9A | 0x9B => {
+                // CPY
+                let value = self.y_register.wrapping_sub(operand);
+                self.status_register.carry = self.y_register >= operand;
+                self.set_status_register(value);
+            }
+
+            // Audio Register Manipulation
+            0xA0..=0xAF | 0xB0..=0xBF => {
+                // AWI0 ... AWIF
+                // Through the bus like any other write, so that watchpoints and the trace see it
+                let index = (opcode & 0x0F) | 0xA0;
+                self.write(index, operand)?;
+            }
+
+            // No-ops and Waits
+            0xF0 | 0xF1 => {
+                // BEAT
+                // Bits 7..5 choose the subdivision (0 = beats, 1 = 8ths, 2 = 16ths, ...)
+                // and bits 4..0 are how many of them to wait, counted from the last grid line
+                let subdivisions = (1 << (operand >> 5)) as f64;
+                let count = (operand & 0b0001_1111) as f64;
+
+                if count > 0.0 {
+                    // The small offset keeps rounding error from putting us just before a grid line
+                    let grid_line = (self.beat_position * subdivisions + 1e-9).floor();
+                    self.beat_target = Some((grid_line + count) / subdivisions);
+                }
+            }
+            0xF2 | 0xF3 => {
+                // NOOP
+                self.cycles_waiting = operand;
+            }
+
+            // Unimplemented
+            _ => return Err(CpuFault::IllegalOpcode(opcode)),
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler, debug::Watchpoint, interrupt::MidiEventKind, isa};
+
+    fn machine(source: &str) -> Machine {
+        let mut rom = Rom::default();
+        rom.banks[0].copy_from_slice(&assembler::assemble(source).unwrap());
+
+        let mut machine = Machine::new(rom);
+        machine.clock_running = true;
+        machine
+    }
+
+    // Like the host, stops when the clock does
+    fn run(machine: &mut Machine, instructions: usize) {
+        for _ in 0..instructions {
+            if !machine.clock_running {
+                break;
+            }
+            machine.execute();
+        }
+    }
+
+    fn note_on(note: u8) -> MidiEvent {
+        MidiEvent {
+            kind: MidiEventKind::NoteOn,
+            data_1: note,
+            data_2: 100,
+        }
+    }
+
+    // Enables note on interrupts, then waits however the test needs to
+    const NOTE_HANDLER: &str = "
+            LOAD #$01
+            STOR $B3
+            LOAD #handler
+            STOR $B4
+            JMP  wait
+        handler:
+            LOAD $B1
+            STOR $80
+            RETI
+        wait:
+    ";
+
+    #[test]
+    fn interrupts_cut_noop_short() {
+        let mut machine = machine(&format!("{}\nNOOP #$FF\nJMP wait", NOTE_HANDLER));
+        run(&mut machine, 6);
+        assert_eq!(machine.cycles_waiting, 0xFF);
+
+        machine.queue_interrupt(note_on(60));
+        run(&mut machine, 2);
+        assert_eq!(machine.ram[0], 60);
+    }
+
+    #[test]
+    fn interrupts_cut_beat_short() {
+        let mut machine = machine(&format!("{}\nBEAT #$01\nJMP wait", NOTE_HANDLER));
+        machine.set_beat_position(0.0);
+        run(&mut machine, 6);
+        assert!(machine.waiting_for_beat());
+
+        machine.queue_interrupt(note_on(60));
+        assert!(!machine.waiting_for_beat());
+        run(&mut machine, 2);
+        assert_eq!(machine.ram[0], 60);
+    }
+
+    #[test]
+    fn interrupts_wake_from_halt() {
+        let mut machine = machine(&format!("{}\nHALT\nJMP wait", NOTE_HANDLER));
+        run(&mut machine, 6);
+        assert!(!machine.clock_running);
+
+        machine.queue_interrupt(note_on(60));
+        assert!(machine.clock_running);
+        run(&mut machine, 3);
+        assert_eq!(machine.ram[0], 60);
+
+        // RETI goes back to the instruction after HALT
+        assert_eq!(machine.instruction_pointer, 0x12);
+    }
+
+    #[test]
+    fn paused_machines_stay_paused() {
+        let mut machine = machine(&format!("{}\nHALT\nJMP wait", NOTE_HANDLER));
+        run(&mut machine, 6);
+        machine.pause();
+
+        machine.queue_interrupt(note_on(60));
+        assert!(!machine.clock_running);
+    }
+
+    #[test]
+    fn handled_faults_resume() {
+        let mut machine = machine(
+            "
+                LOAD #handler
+                STOR $B9
+            again:
+                STOR $00        ; write to ROM
+                INC  $81
+                LOAD $81
+                CMP  #$02
+                BRNE again
+                HALT
+            handler:
+                LOAD $BA
+                STOR $80
+                RET
+            ",
+        );
+        run(&mut machine, 32);
+
+        assert!(machine.fault.is_none());
+        assert_eq!(machine.ram[0], CpuFault::WriteProtect(0x00).code());
+        // The handler ran for both faults, and the program carried on after each
+        assert_eq!(machine.ram[1], 2);
+        assert!(machine.halted);
+
+        machine.run();
+        assert!(machine.clock_running);
+    }
+
+    #[test]
+    fn faults_in_the_handler_halt() {
+        let mut machine = machine(
+            "
+                LOAD #handler
+                STOR $B9
+                STOR $00
+                HALT
+            handler:
+                STOR $01
+                RET
+            ",
+        );
+        run(&mut machine, 8);
+
+        let fault = machine.fault.expect("the second fault should halt");
+        assert_eq!(fault.instruction_pointer, 0x08);
+        assert!(!machine.clock_running);
+        assert_eq!(machine.instruction_pointer, 0x08);
+    }
+
+    #[test]
+    fn faulting_writes_are_not_traced() {
+        let mut machine = machine("STOR $80\nSTOR $00");
+        run(&mut machine, 2);
+        assert!(machine.fault.is_some());
+
+        let writes: Vec<u8> = machine
+            .trace
+            .drain()
+            .filter_map(|entry| match entry.event {
+                TraceEvent::Write { address, .. } => Some(address),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(writes, [0x80]);
+    }
+
+    #[test]
+    fn step_back_goes_back_one_instruction() {
+        let mut machine = machine("loop:\nINC $80\nJMP loop");
+        machine.history.interval = 4;
+        run(&mut machine, 10);
+        assert_eq!(machine.ram[0], 5);
+
+        // Between snapshots, so this has to re-execute from the one at instruction 8
+        machine.step_back();
+        assert_eq!(machine.history.instructions_executed, 9);
+        assert_eq!(machine.ram[0], 5);
+        assert_eq!(machine.instruction_pointer, 0x02);
+
+        machine.step_back();
+        assert_eq!(machine.history.instructions_executed, 8);
+        assert_eq!(machine.ram[0], 4);
+        assert_eq!(machine.instruction_pointer, 0x00);
+
+        // The snapshots after this point are still there to go forward to
+        machine.seek(10);
+        assert_eq!(machine.history.instructions_executed, 10);
+        assert_eq!(machine.ram[0], 5);
+        assert!(!machine.clock_running);
+    }
+
+    #[test]
+    fn step_back_replays_interrupts() {
+        let mut machine = machine(&format!("{}\nINC $81\nJMP wait", NOTE_HANDLER));
+        run(&mut machine, 10);
+
+        machine.queue_interrupt(note_on(60));
+        run(&mut machine, 10);
+        let (ram, stack, instruction_pointer) =
+            (machine.ram, machine.stack, machine.instruction_pointer);
+        assert_eq!(ram[0], 60);
+
+        // The last periodic snapshot is from before the interrupt
+        run(&mut machine, 1);
+        machine.step_back();
+        assert_eq!(machine.history.instructions_executed, 20);
+        assert_eq!(machine.ram, ram);
+        assert_eq!(machine.stack, stack);
+        assert_eq!(machine.instruction_pointer, instruction_pointer);
+        assert!(!machine.interrupts.servicing);
+    }
+
+    #[test]
+    fn sound_writes_trip_watchpoints() {
+        let mut machine = machine("AWI3 #$08\nHALT");
+        machine.debugger.set_watchpoint(
+            0xA3,
+            Watchpoint {
+                read: false,
+                write: true,
+            },
+        );
+        run(&mut machine, 2);
+
+        assert!(!machine.clock_running);
+        assert_eq!(machine.instruction_pointer, 0x02);
+        assert_eq!(machine.debugger.last_writers.get(0xA3), Some((0, 0x00)));
+        assert_eq!(machine.sound.read(0xA3).ok(), Some(0x08));
+    }
+
+    #[test]
+    fn shifts_past_the_width_clear_the_accumulator() {
+        let mut machine = machine("LOAD #$FF\nLSL #$08\nLOAD #$FF\nLSR #$09");
+        run(&mut machine, 2);
+        assert_eq!(machine.accumulator, 0);
+        assert!(machine.status_register.zero);
+        run(&mut machine, 2);
+        assert_eq!(machine.accumulator, 0);
+    }
+
+    #[test]
+    fn mirrors_read_and_write_the_same_device() {
+        let mut machine = machine(
+            "
+                LOAD #$2A
+                STOR $C4        ; the interrupt vector
+                LOAD $D0        ; the first byte of ROM
+                STOR $80
+                LOAD $C4
+                STOR $81
+            ",
+        );
+        machine.bus.map(0xC0..=0xC4, Device::Interrupts).unwrap();
+        machine.bus.map(0xD0..=0xDF, Device::Rom).unwrap();
+        run(&mut machine, 6);
+
+        assert!(machine.fault.is_none());
+        assert_eq!(machine.interrupts.vector, 0x2A);
+        assert_eq!(machine.ram[..2], [0x10, 0x2A]);
+    }
+
+    // The disassembler and assembler work from isa::INSTRUCTIONS, so it has to agree with step
+    #[test]
+    fn isa_covers_every_opcode() {
+        for opcode in 0..=0xFF {
+            let word = (opcode as u16) << 8 | 0x80;
+
+            let mut rom = Rom::default();
+            rom.banks[0][0] = word;
+            let mut machine = Machine::new(rom);
+
+            let illegal = matches!(machine.step(), Err(CpuFault::IllegalOpcode(_)));
+            assert_eq!(
+                illegal,
+                isa::disassemble(word).is_none(),
+                "opcode 0x{:02X}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn add_sets_carry_and_overflow() {
+        let mut machine = machine("LOAD #$7F\nADD #$01\nLOAD #$FF\nADD #$01");
+
+        run(&mut machine, 2);
+        assert_eq!(machine.accumulator, 0x80);
+        assert!(machine.status_register.overflow);
+        assert!(machine.status_register.negative);
+        assert!(!machine.status_register.carry);
+
+        run(&mut machine, 2);
+        assert_eq!(machine.accumulator, 0x00);
+        assert!(!machine.status_register.overflow);
+        assert!(machine.status_register.zero);
+        assert!(machine.status_register.carry);
+    }
+
+    #[test]
+    fn sub_clears_carry_on_borrow() {
+        let mut machine = machine("LOAD #$05\nSUB #$06\nLOAD #$80\nSUB #$01");
+
+        run(&mut machine, 2);
+        assert_eq!(machine.accumulator, 0xFF);
+        assert!(!machine.status_register.carry);
+        assert!(!machine.status_register.overflow);
+        assert!(machine.status_register.negative);
+
+        run(&mut machine, 2);
+        assert_eq!(machine.accumulator, 0x7F);
+        assert!(machine.status_register.carry);
+        assert!(machine.status_register.overflow);
+    }
+
+    #[test]
+    fn adc_and_sbc_chain_through_the_carry() {
+        // 0x01FF + 0x0001, then 0x0200 - 0x0001, low byte first
+        let mut machine = machine(
+            "
+                CLC
+                LOAD #$FF
+                ADC  #$01
+                STOR $80
+                LOAD #$01
+                ADC  #$00
+                STOR $81
+                SEC
+                LOAD $80
+                SBC  #$01
+                STOR $82
+                LOAD $81
+                SBC  #$00
+                STOR $83
+            ",
+        );
+        run(&mut machine, 14);
+
+        assert_eq!(machine.ram[..4], [0x00, 0x02, 0xFF, 0x01]);
+        assert!(machine.status_register.carry);
+    }
+
+    #[test]
+    fn call_returns_past_itself() {
+        let mut machine = machine("CALL sub\nSTOR $80\nHALT\nsub: LOAD #$2A\nRET");
+        run(&mut machine, 5);
+
+        assert!(!machine.clock_running);
+        assert_eq!(machine.ram[0], 0x2A);
+        assert_eq!(machine.stack_pointer, STACK_TOP);
+    }
+
+    #[test]
+    fn pull_takes_the_last_push() {
+        let mut machine = machine(
+            "
+                LOAD #$11
+                PUSH
+                LOAD #$22
+                PUSH
+                PULL
+                STOR $80
+                PULL
+                STOR $81
+            ",
+        );
+        run(&mut machine, 8);
+
+        assert_eq!(machine.ram[..2], [0x22, 0x11]);
+        assert_eq!(machine.stack_pointer, STACK_TOP);
+    }
+
+    #[test]
+    fn pushing_onto_a_full_stack_faults() {
+        let mut machine = machine(&"PUSH\n".repeat(17));
+        run(&mut machine, 17);
+
+        let fault = machine.fault.expect("the 17th push should fault");
+        assert!(matches!(fault.fault, CpuFault::StackOverflow(_)));
+        assert_eq!(fault.instruction_pointer, 0x20);
+        assert_eq!(machine.stack_pointer, STACK_BOTTOM - 1);
+    }
+
+    #[test]
+    fn pulling_from_an_empty_stack_faults() {
+        let mut machine = machine("RET");
+        run(&mut machine, 1);
+
+        let fault = machine.fault.expect("there's nothing to return to");
+        assert!(matches!(fault.fault, CpuFault::StackUnderflow(_)));
+        assert_eq!(machine.stack_pointer, STACK_TOP);
+    }
+
+    #[test]
+    fn reti_restores_the_interrupted_state() {
+        let mut machine = machine(&format!("{}\nSEC\nLOAD #$80\nloop: JMP loop", NOTE_HANDLER));
+        run(&mut machine, 7);
+        let interrupted_at = machine.instruction_pointer;
+
+        machine.queue_interrupt(note_on(60));
+        run(&mut machine, 2);
+        assert!(machine.interrupts.servicing);
+        assert_eq!(machine.ram[0], 60);
+        assert!(!machine.status_register.negative);
+
+        run(&mut machine, 1);
+        assert!(!machine.interrupts.servicing);
+        assert!(machine.status_register.carry);
+        assert!(machine.status_register.negative);
+        assert_eq!(machine.instruction_pointer, interrupted_at);
+        assert_eq!(machine.stack_pointer, STACK_TOP);
+    }
+
+    #[test]
+    fn reti_restores_the_interrupted_bank() {
+        let mut rom = Rom::default();
+        rom.banks[0].copy_from_slice(
+            &assembler::assemble(
+                "
+                    LOAD #$01
+                    STOR $B3
+                    LOAD #handler
+                    STOR $B4
+                wait:
+                    JMP  wait
+                handler:
+                    LOAD #$01
+                    FJMP $00
+                ",
+            )
+            .unwrap(),
+        );
+        // The rest of the handler is in bank 1
+        rom.banks[1].copy_from_slice(&assembler::assemble("LOAD $B1\nSTOR $80\nRETI").unwrap());
+
+        let mut machine = Machine::new(rom);
+        machine.clock_running = true;
+        run(&mut machine, 5);
+
+        machine.queue_interrupt(note_on(60));
+        run(&mut machine, 2);
+        assert_eq!(machine.rom_bank, 1);
+
+        run(&mut machine, 3);
+        assert_eq!(machine.ram[0], 60);
+        assert_eq!(machine.rom_bank, 0);
+        assert_eq!(machine.instruction_pointer, 0x08);
+        assert_eq!(machine.stack_pointer, STACK_TOP);
+    }
+
+    #[test]
+    fn interrupts_wait_for_the_handler_to_return() {
+        let mut machine = machine(&format!("{}\nJMP wait", NOTE_HANDLER));
+        run(&mut machine, 5);
+
+        machine.queue_interrupt(note_on(60));
+        machine.queue_interrupt(note_on(64));
+        run(&mut machine, 3);
+        assert_eq!(machine.ram[0], 60);
+
+        run(&mut machine, 3);
+        assert_eq!(machine.ram[0], 64);
+    }
+
+    #[test]
+    fn masked_events_are_ignored() {
+        let mut machine = machine(&format!("{}\nJMP wait", NOTE_HANDLER));
+        run(&mut machine, 5);
+
+        machine.queue_interrupt(MidiEvent {
+            kind: MidiEventKind::NoteOff,
+            data_1: 60,
+            data_2: 0,
+        });
+        run(&mut machine, 3);
+        assert!(!machine.interrupts.servicing);
+        assert_eq!(machine.ram[0], 0);
+    }
+}
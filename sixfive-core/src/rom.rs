@@ -0,0 +1,48 @@
+// Four banks of 64 instruction words, plus the trampoline vectors. The machine owns its copy,
+// so hosts that edit the program (like the plugin's editor) send changes to it rather than
+// having it read from shared storage while it runs.
+#[derive(Clone)]
+pub struct Rom {
+    pub banks: [[u16; 0x40]; 4],
+    pub trampoline_vectors: [u8; 4],
+    // Bank selected at power-on and after a reset
+    pub power_on_bank: usize,
+}
+
+impl Default for Rom {
+    fn default() -> Self {
+        Self {
+            banks: [[0; 0x40]; 4],
+            trampoline_vectors: [0; 4],
+            power_on_bank: 0,
+        }
+    }
+}
+
+// Instruction words are stored big-endian: opcode at the even address, argument at the odd one
+pub fn read_word(bank: &[u16], address: u8) -> u8 {
+    if address & 0b1 == 0 {
+        (bank[address as usize / 2] >> 8) as u8
+    } else {
+        bank[address as usize / 2] as u8
+    }
+}
+
+impl Rom {
+    // 0x00..=0x7F, in the given bank
+    pub fn read_rom(&self, bank: usize, address: u8) -> u8 {
+        read_word(&self.banks[bank], address)
+    }
+
+    // 0xFC..=0xFF
+    pub fn read_trampoline_vector(&self, address: u8) -> u8 {
+        self.trampoline_vectors[(address - 0xFC) as usize]
+    }
+
+    // Copies in as many words as fit, leaving the rest of the bank alone
+    pub fn load_bank(&mut self, bank: usize, words: &[u16]) {
+        for (word, value) in self.banks[bank].iter_mut().zip(words) {
+            *word = *value;
+        }
+    }
+}
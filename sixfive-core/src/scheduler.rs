@@ -0,0 +1,154 @@
+// Runs the machine from a host's audio callback: works out which instructions are due in each
+// sample (at clock speeds above the sample rate, several are), and keeps BEAT in step with the
+// host's transport. The plugin and sixfive-render both drive the machine through this, so that
+// renders match what the plugin plays.
+
+use crate::Machine;
+
+// Guards against a runaway clock (e.g. a huge tempo-synced tick rate) starving the audio thread
+const MAX_INSTRUCTIONS_PER_SAMPLE: usize = 256;
+
+#[derive(Clone, Copy)]
+pub struct Transport {
+    pub tempo: f64,
+    pub playing: bool,
+    // In quarter notes, if the host reports it
+    pub position: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    sample_rate: f64,
+    samples_per_instruction: f64,
+    samples_until_execute: f64,
+    instruction_budget: usize,
+
+    // Transport position in quarter notes, used to keep BEAT locked to the host
+    beat_position: f64,
+    beats_per_sample: f64,
+
+    // Instructions executed since the host last took the count, to measure the clock speed
+    instructions_executed: u64,
+}
+
+impl Scheduler {
+    // Called at the start of each buffer, with the clock speed in instructions per second
+    pub fn begin_buffer(
+        &mut self,
+        samples: usize,
+        sample_rate: f64,
+        clock_speed: f64,
+        transport: Transport,
+    ) {
+        self.sample_rate = sample_rate;
+
+        if let Some(position) = transport.position {
+            self.beat_position = position;
+        }
+
+        // If the host doesn't report a position, keep counting beats ourselves
+        self.beats_per_sample = if transport.playing {
+            transport.tempo / 60.0 / sample_rate
+        } else {
+            0.0
+        };
+
+        // Recomputed every buffer so that a tempo-synced clock follows tempo changes
+        self.samples_per_instruction = sample_rate / clock_speed;
+
+        self.instruction_budget = MAX_INSTRUCTIONS_PER_SAMPLE * samples;
+    }
+
+    // Runs whatever is due before the next sample, then generates it
+    pub fn process_sample(&mut self, machine: &mut Machine) -> f32 {
+        machine.timestamp += 1;
+        machine.set_beat_position(self.beat_position);
+        self.beat_position += self.beats_per_sample;
+
+        if machine.waiting_for_beat() {
+            // Execute on the exact sample that the beat arrives
+            self.samples_until_execute = 0.0;
+        } else if machine.clock_running {
+            while self.samples_until_execute <= 0.0
+                && machine.clock_running
+                && !machine.waiting_for_beat()
+            {
+                if self.instruction_budget == 0 {
+                    // Drop the backlog instead of trying to catch up in the next buffer
+                    self.samples_until_execute = 0.0;
+                    break;
+                }
+
+                machine.execute();
+
+                self.instruction_budget -= 1;
+                self.instructions_executed += 1;
+                self.samples_until_execute += self.samples_per_instruction;
+            }
+
+            self.samples_until_execute -= 1.0;
+        } else {
+            self.samples_until_execute = 0.0;
+        }
+
+        machine.sound.generate(self.sample_rate)
+    }
+
+    // Instructions executed since this was last called
+    pub fn take_instructions_executed(&mut self) -> u64 {
+        std::mem::take(&mut self.instructions_executed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler, Rom};
+
+    fn spin() -> Machine {
+        let mut rom = Rom::default();
+        rom.banks[0].copy_from_slice(&assembler::assemble("loop: JMP loop").unwrap());
+
+        let mut machine = Machine::new(rom);
+        machine.clock_running = true;
+        machine
+    }
+
+    fn transport() -> Transport {
+        Transport {
+            tempo: 120.0,
+            playing: true,
+            position: None,
+        }
+    }
+
+    #[test]
+    fn instructions_are_spread_across_samples() {
+        let mut machine = spin();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.begin_buffer(400, 400.0, 100.0, transport());
+        for _ in 0..400 {
+            scheduler.process_sample(&mut machine);
+        }
+
+        assert_eq!(scheduler.take_instructions_executed(), 100);
+        assert_eq!(scheduler.take_instructions_executed(), 0);
+    }
+
+    #[test]
+    fn runaway_clocks_are_capped() {
+        let mut machine = spin();
+        let mut scheduler = Scheduler::default();
+
+        scheduler.begin_buffer(4, 400.0, 1e9, transport());
+        for _ in 0..4 {
+            scheduler.process_sample(&mut machine);
+        }
+
+        assert_eq!(
+            scheduler.take_instructions_executed(),
+            (MAX_INSTRUCTIONS_PER_SAMPLE * 4) as u64
+        );
+    }
+}
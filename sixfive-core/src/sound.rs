@@ -0,0 +1,743 @@
+use crate::{
+    apu::{Apu, CPU_FREQUENCY},
+    bus::Peripheral,
+    machine::CpuFault,
+};
+
+const CHANNEL_MAX_VOLUME: f32 = 15.0;
+
+// CPU cycles between noise shifts, indexed by the low 4 bits of the noise channel's register 2
+pub const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+pub mod conversions {
+    pub fn note_period_to_seconds(period: u16) -> f64 {
+        //  fCPU / (16 × (t + 1))
+        // TODO: We probably don't exactly have to match the NES here
+        // (we might as well pick something easier to work with)
+        let frequency = 1789773.0 / (16.0 * (period as f64 + 1.0));
+        1.0 / frequency
+    }
+
+    // Time between shifts of the noise channel's shift register
+    pub fn noise_period_to_seconds(period: u16) -> f64 {
+        super::NOISE_PERIODS[(period & 0x0F) as usize] as f64 / super::CPU_FREQUENCY
+    }
+
+    pub fn note_length_to_seconds(length: u8) -> f64 {
+        (length as f64) * 1.0 / 60.0 // TODO: this doesn't match NES (uses a lookup table). Do something similar?
+    }
+
+    pub fn envelope_length_to_seconds(length: u8) -> f64 {
+        (length as f64) * 1.0 / 15.0
+    }
+
+    // The sweep unit steps every 2 ^ speed half frames (120 per second)
+    pub fn seconds_to_shift_steps(seconds: f64, speed: u8) -> u32 {
+        (seconds * 120.0) as u32 >> speed
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ChannelRegisters {
+    // Register 0 (envelope)
+    pub duty_cycle: u8,
+    pub looping: bool,
+    pub envelope: bool,
+    pub envelope_length: u8, // constant volume, if envelope not used
+
+    // Register 1 (shift unit)
+    pub shift_enabled: bool,
+    pub shift_period: u8, // shift amount: each step changes the period by period >> this
+    pub shift_reverse: bool, // 0 = lengthen period / lower note, 1 = shorten period / higher note
+    pub shift_speed: u8,  // this value is exponential
+
+    // Register 2, 3 (period, note length)
+    pub period: u16,
+    pub note_length: u8,
+
+    // Internal registers
+    time_since_note: f64,
+    time_since_sweep: f64,
+    sweep_steps: u32,
+    // The period after any sweeping, and whether the sweep unit has silenced the channel
+    swept_period: u16,
+    sweep_muted: bool,
+    // Only the pulse channels have sweep units
+    sweeps: bool,
+}
+
+impl Default for ChannelRegisters {
+    fn default() -> Self {
+        Self {
+            duty_cycle: 0,
+            looping: false,
+            envelope: false,
+            envelope_length: 0,
+            shift_enabled: false,
+            shift_period: 0,
+            shift_reverse: false,
+            shift_speed: 0,
+            period: 0,
+            note_length: 0,
+
+            time_since_note: 0.0,
+            time_since_sweep: 0.0,
+            sweep_steps: 0,
+            swept_period: 0,
+            sweep_muted: true,
+            sweeps: true,
+        }
+    }
+}
+
+impl ChannelRegisters {
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            0x00 => {
+                let mut value = 0;
+                value |= self.duty_cycle << 6;
+                value |= (self.looping as u8) << 5;
+                value |= (self.envelope as u8) << 4;
+                value |= self.envelope_length;
+                value
+            }
+            0x01 => {
+                let mut value = 0;
+                value |= (self.shift_enabled as u8) << 7;
+                value |= self.shift_period << 4;
+                value |= (self.shift_reverse as u8) << 3;
+                value |= self.shift_speed;
+                value
+            }
+            0x02 => {
+                let mut value = 0;
+                value |= self.period as u8;
+                value
+            }
+            0x03 => {
+                let mut value = 0;
+                value |= (self.period >> 8) as u8;
+                value |= self.note_length << 3;
+                value
+            }
+            _ => panic!("Read from invalid register: {:02X}", register),
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x00 => {
+                self.duty_cycle = (value >> 6) & 0b11;
+                self.looping = (value >> 5) & 0b1 == 1;
+                self.envelope = (value >> 4) & 0b1 == 1;
+                self.envelope_length = value & 0b1111;
+            }
+            0x01 => {
+                self.shift_enabled = (value >> 7) & 0b1 == 1;
+                self.shift_period = (value >> 4) & 0b111;
+                self.shift_reverse = (value >> 3) & 0b1 == 1;
+                self.shift_speed = value & 0b111;
+                self.restart_sweep();
+            }
+            0x02 => {
+                self.period = (self.period & 0b111_0000_0000) | value as u16;
+                self.swept_period = self.period;
+            }
+            0x03 => {
+                self.period = (self.period & 0b000_1111_1111) | (((value & 0b0111) as u16) << 8);
+                self.note_length = (value >> 3) & 0b1111_1;
+                self.time_since_note = 0.0;
+                self.swept_period = self.period;
+                self.restart_sweep();
+            }
+            _ => panic!("Write to invalid register: {:02X}", register),
+        };
+
+        self.update_sweep_muted();
+    }
+
+    fn update_sweep_muted(&mut self) {
+        self.sweep_muted = self.sweeps && self.sweep_muted(self.swept_period, false);
+    }
+
+    fn restart_sweep(&mut self) {
+        self.time_since_sweep = 0.0;
+        self.sweep_steps = 0;
+    }
+
+    fn tick(&mut self, sample_rate: f64) {
+        self.time_since_note += 1.0 / sample_rate;
+        self.time_since_sweep += 1.0 / sample_rate;
+
+        // Catch the sweep unit up with however many steps are due
+        let steps = conversions::seconds_to_shift_steps(self.time_since_sweep, self.shift_speed);
+        while self.sweep_steps < steps {
+            self.sweep_steps += 1;
+
+            if self.sweeps
+                && self.shift_enabled
+                && self.shift_period > 0
+                && !self.sweep_muted(self.swept_period, false)
+            {
+                self.swept_period = self.sweep_target(self.swept_period, false);
+            }
+        }
+
+        self.update_sweep_muted();
+    }
+
+    // Where the next sweep step would take the period. Pulse 1 negates with ones' complement in
+    // accurate mode, so sweeping up lands one lower on it than on the other channels.
+    pub fn sweep_target(&self, period: u16, ones_complement: bool) -> u16 {
+        let change = period >> self.shift_period;
+
+        if !self.shift_reverse {
+            period + change
+        } else if ones_complement {
+            period.saturating_sub(change + 1)
+        } else {
+            period.saturating_sub(change)
+        }
+    }
+
+    // Like the NES, the channel is silenced when its period is too high to hear, or when the
+    // sweep would take it past 0x7FF (even if sweeping is disabled)
+    pub fn sweep_muted(&self, period: u16, ones_complement: bool) -> bool {
+        period < 8 || self.sweep_target(period, ones_complement) > 0x7FF
+    }
+
+    // The live period and mute state, as the channel is currently playing
+    pub fn swept_period(&self) -> u16 {
+        self.swept_period
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.sweep_muted
+    }
+
+    // Used by the accurate mode, which runs its own sweep units
+    pub(crate) fn set_sweep_state(&mut self, swept_period: u16, muted: bool) {
+        self.swept_period = swept_period;
+        self.sweep_muted = muted;
+    }
+
+    fn get_effective_volume(&self) -> f32 {
+        // Are we outside a note? (The loop flag halts the length counter, so notes hold.)
+        if !self.looping
+            && self.time_since_note > conversions::note_length_to_seconds(self.note_length)
+        {
+            return 0.0;
+        }
+
+        // Is the envelope active?
+        if self.envelope {
+            let elapsed = self.time_since_note;
+            let total = conversions::envelope_length_to_seconds(self.envelope_length);
+
+            // Without the loop flag, the envelope decays once and then holds at zero
+            if total == 0.0 || (!self.looping && elapsed >= total) {
+                return 0.0;
+            }
+
+            let relative = (elapsed % total) / total;
+            let relative = 1.0 - relative;
+
+            return relative as f32;
+        }
+
+        // Otherwise, use the constant volume
+        self.envelope_length as f32 / CHANNEL_MAX_VOLUME
+    }
+
+    // The noise channel uses register 2 differently: bit 7 selects the short ("metallic") mode,
+    // and bits 0..=3 pick a period from NOISE_PERIODS
+    pub fn noise_period(&self) -> u16 {
+        NOISE_PERIODS[(self.period & 0x0F) as usize]
+    }
+
+    pub fn noise_short_mode(&self) -> bool {
+        self.period & 0x80 != 0
+    }
+
+    pub fn sweeps(&self) -> bool {
+        self.sweeps
+    }
+
+    fn get_effective_period(&self) -> u16 {
+        self.swept_period
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OscillatorQuality {
+    // Waveforms computed straight from the phase, which alias at high pitches
+    Naive,
+    // Discontinuities smoothed with PolyBLEP (pulse) and PolyBLAMP (triangle) corrections
+    BandLimited,
+}
+
+// Correction for a step of height 1 at phase 0, given the phase advanced per sample
+fn poly_blep(phase: f64, increment: f64) -> f64 {
+    if phase < increment {
+        let t = phase / increment;
+        -0.5 * (1.0 - t) * (1.0 - t)
+    } else if phase > 1.0 - increment {
+        let t = (phase - 1.0) / increment;
+        0.5 * (1.0 + t) * (1.0 + t)
+    } else {
+        0.0
+    }
+}
+
+// Correction for the slope increasing by 1 per sample at phase 0 (the integral of poly_blep)
+fn poly_blamp(phase: f64, increment: f64) -> f64 {
+    if phase < increment {
+        let t = phase / increment;
+        (1.0 - t).powi(3) / 6.0
+    } else if phase > 1.0 - increment {
+        let t = (phase - 1.0) / increment;
+        (1.0 + t).powi(3) / 6.0
+    } else {
+        0.0
+    }
+}
+
+pub trait WaveGenerator: Default {
+    // Whether the channel has a sweep unit, which can also mute it
+    const SWEEPS: bool;
+
+    fn generate(
+        &mut self,
+        registers: &mut ChannelRegisters,
+        sample_rate: f64,
+        quality: OscillatorQuality,
+    ) -> f32;
+}
+
+#[derive(Clone)]
+pub struct SquareWave {}
+
+impl Default for SquareWave {
+    fn default() -> Self {
+        Self {}
+    }
+}
+
+impl WaveGenerator for SquareWave {
+    const SWEEPS: bool = true;
+
+    fn generate(
+        &mut self,
+        registers: &mut ChannelRegisters,
+        sample_rate: f64,
+        quality: OscillatorQuality,
+    ) -> f32 {
+        if registers.sweep_muted {
+            return 0.0;
+        }
+
+        let elapsed = registers.time_since_note;
+        let period = conversions::note_period_to_seconds(registers.get_effective_period());
+        let relative = (elapsed % period) / period;
+
+        let duty = match registers.duty_cycle {
+            0b00 => 0.125,
+            0b01 => 0.25,
+            0b10 => 0.5,
+            0b11 => 0.75,
+            _ => panic!("Invalid duty cycle: {}", registers.duty_cycle),
+        };
+
+        let mut value = if relative < duty { 0.0 } else { 1.0 };
+
+        // Above half the sample rate there's nothing left to correct
+        let increment = 1.0 / (sample_rate * period);
+        if quality == OscillatorQuality::BandLimited && increment < 0.5 {
+            // Rises at the duty cycle, falls as the period wraps
+            value += poly_blep((relative - duty).rem_euclid(1.0), increment);
+            value -= poly_blep(relative, increment);
+        }
+
+        value as f32 * registers.get_effective_volume()
+    }
+}
+
+#[derive(Clone)]
+pub struct TriangleWave {}
+
+impl Default for TriangleWave {
+    fn default() -> Self {
+        Self {}
+    }
+}
+
+impl WaveGenerator for TriangleWave {
+    const SWEEPS: bool = false;
+
+    fn generate(
+        &mut self,
+        registers: &mut ChannelRegisters,
+        sample_rate: f64,
+        quality: OscillatorQuality,
+    ) -> f32 {
+        let elapsed = registers.time_since_note;
+        let period = conversions::note_period_to_seconds(registers.get_effective_period());
+        let relative = (elapsed % period) / period;
+
+        let mut value = if relative < 0.5 {
+            relative * 2.0
+        } else {
+            (1.0 - relative) * 2.0
+        };
+
+        let increment = 1.0 / (sample_rate * period);
+        if quality == OscillatorQuality::BandLimited && increment < 0.5 {
+            // The slope changes by 4 per period at each corner: up at the bottom, down at the top
+            value += 4.0 * increment * poly_blamp(relative, increment);
+            value -= 4.0 * increment * poly_blamp((relative - 0.5).rem_euclid(1.0), increment);
+        }
+
+        value as f32 * registers.get_effective_volume()
+    }
+}
+
+// Short mode takes feedback from bit 6 instead of bit 1, which repeats after 93 shifts
+pub(crate) fn clock_shift_register(shift_register: &mut u16, short_mode: bool) {
+    let tap = if short_mode { 6 } else { 1 };
+    let feedback = (*shift_register & 0b1) ^ ((*shift_register >> tap) & 0b1);
+    *shift_register >>= 1;
+    *shift_register |= feedback << 14;
+}
+
+#[derive(Clone)]
+pub struct Noise {
+    shift_register: u16,
+    // CPU cycles since the last shift
+    cycles: f64,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            shift_register: 1,
+            cycles: 0.0,
+        }
+    }
+}
+
+impl WaveGenerator for Noise {
+    // The period is a table index, which doesn't sweep
+    const SWEEPS: bool = false;
+
+    fn generate(
+        &mut self,
+        registers: &mut ChannelRegisters,
+        sample_rate: f64,
+        _quality: OscillatorQuality,
+    ) -> f32 {
+        // Counted in CPU cycles rather than samples, so the pitch doesn't depend on the host
+        self.cycles += CPU_FREQUENCY / sample_rate;
+
+        let period = registers.noise_period() as f64;
+        while self.cycles >= period {
+            self.cycles -= period;
+            clock_shift_register(&mut self.shift_register, registers.noise_short_mode());
+        }
+
+        if self.shift_register & 0b1 == 0 {
+            registers.get_effective_volume()
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Channel<T: WaveGenerator> {
+    registers: ChannelRegisters,
+    generator: T,
+}
+
+impl<T: WaveGenerator> Default for Channel<T> {
+    fn default() -> Self {
+        let mut registers = ChannelRegisters {
+            sweeps: T::SWEEPS,
+            ..ChannelRegisters::default()
+        };
+        registers.update_sweep_muted();
+
+        Self {
+            registers,
+            generator: T::default(),
+        }
+    }
+}
+
+impl<T: WaveGenerator> Channel<T> {
+    fn read(&self, register: u8) -> u8 {
+        self.registers.read(register)
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        self.registers.write(register, value)
+    }
+
+    pub fn generate(&mut self, sample_rate: f64, quality: OscillatorQuality) -> f32 {
+        self.registers.tick(sample_rate);
+        self.generator
+            .generate(&mut self.registers, sample_rate, quality)
+    }
+
+    pub fn registers(&self) -> &ChannelRegisters {
+        &self.registers
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SoundMode {
+    // Envelopes, sweeps and note lengths worked out from the time since each note started
+    Simple,
+    // Clocked like the NES APU, with a frame sequencer (see apu.rs)
+    Accurate,
+}
+
+// Frame sequencer control, outside the channels' registers. Bit 7 selects the 5-step sequence.
+pub const FRAME_COUNTER: u8 = 0xB5;
+
+#[derive(Clone)]
+pub struct SoundChip {
+    pub square_wave_1: Channel<SquareWave>,
+    pub square_wave_2: Channel<SquareWave>,
+    pub triangle_wave: Channel<TriangleWave>,
+    pub noise: Channel<Noise>,
+    pub apu: Apu,
+    pub quality: OscillatorQuality,
+    pub mode: SoundMode,
+}
+
+impl Default for SoundChip {
+    fn default() -> Self {
+        Self {
+            square_wave_1: Channel::default(),
+            square_wave_2: Channel::default(),
+            triangle_wave: Channel::default(),
+            noise: Channel::default(),
+            apu: Apu::default(),
+            quality: OscillatorQuality::Naive,
+            mode: SoundMode::Simple,
+        }
+    }
+}
+
+pub const CHANNEL_NAMES: [&str; 4] = ["Square Wave 1", "Square Wave 2", "Triangle Wave", "Noise"];
+
+// Which channel and fields a write to a sound register changes
+pub fn describe_register(register: u8) -> Option<String> {
+    if register == FRAME_COUNTER {
+        return Some("Frame sequencer: 4 or 5 steps".to_string());
+    }
+
+    if !(0xA0..=0xAF).contains(&register) {
+        return None;
+    }
+
+    let channel = CHANNEL_NAMES[(register as usize - 0xA0) / 4];
+    let fields = match register & 0b11 {
+        0x00 => "duty cycle, looping, envelope, volume",
+        0x01 => "shift enable, period, direction, speed",
+        0x02 if register == 0xAE => "mode, period",
+        0x02 => "period (low)",
+        _ => "period (high), note length",
+    };
+
+    Some(format!("{}: {}", channel, fields))
+}
+
+impl Peripheral for SoundChip {
+    fn read(&self, register: u8) -> Result<u8, CpuFault> {
+        match register {
+            0xA0..=0xA3 => Ok(self.square_wave_1.read(register - 0xA0)),
+            0xA4..=0xA7 => Ok(self.square_wave_2.read(register - 0xA4)),
+            0xA8..=0xAB => Ok(self.triangle_wave.read(register - 0xA8)),
+            0xAC..=0xAF => Ok(self.noise.read(register - 0xAC)),
+            FRAME_COUNTER => Ok((self.apu.five_step() as u8) << 7),
+            _ => Err(CpuFault::BusError(register)),
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> Result<(), CpuFault> {
+        match register {
+            0xA0..=0xA3 => self.square_wave_1.write(register - 0xA0, value),
+            0xA4..=0xA7 => self.square_wave_2.write(register - 0xA4, value),
+            0xA8..=0xAB => self.triangle_wave.write(register - 0xA8, value),
+            0xAC..=0xAF => self.noise.write(register - 0xAC, value),
+            FRAME_COUNTER => {
+                let registers = self.channel_registers();
+                self.apu.set_five_step(value & 0x80 != 0, &registers);
+                return Ok(());
+            }
+            _ => return Err(CpuFault::BusError(register)),
+        }
+
+        // The accurate mode keeps its own state (like length counters) that writes restart
+        let channel = (register as usize - 0xA0) / 4;
+        let registers = self.channel_registers()[channel];
+        self.apu.write(channel, register & 0b11, &registers);
+
+        Ok(())
+    }
+}
+
+impl SoundChip {
+    fn channel_registers(&self) -> [ChannelRegisters; 4] {
+        [
+            self.square_wave_1.registers,
+            self.square_wave_2.registers,
+            self.triangle_wave.registers,
+            self.noise.registers,
+        ]
+    }
+
+    pub fn generate(&mut self, sample_rate: f64) -> f32 {
+        let [square_wave_1, square_wave_2, triangle_wave, noise] = match self.mode {
+            SoundMode::Simple => [
+                self.square_wave_1.generate(sample_rate, self.quality),
+                self.square_wave_2.generate(sample_rate, self.quality),
+                self.triangle_wave.generate(sample_rate, self.quality),
+                self.noise.generate(sample_rate, self.quality),
+            ],
+            SoundMode::Accurate => {
+                let registers = self.channel_registers();
+                let output = self.apu.generate(sample_rate, &registers);
+
+                // Keep the registers' view of the sweep up to date, for the editor
+                let channels = [
+                    &mut self.square_wave_1.registers,
+                    &mut self.square_wave_2.registers,
+                    &mut self.triangle_wave.registers,
+                    &mut self.noise.registers,
+                ];
+                for (channel, registers) in channels.into_iter().enumerate() {
+                    let (period, muted) = self.apu.sweep_state(channel, registers);
+                    registers.set_sweep_state(period, muted);
+                }
+
+                output
+            }
+        };
+
+        // Taken from https://www.nesdev.org/wiki/APU_Mixer#Linear_Approximation
+        // Note that the * 16 is because our generate() calls return from 0.0 - 1.0, not 0 - 15
+        (0.00376 * 16.0) * square_wave_1
+            + (0.00376 * 16.0) * square_wave_2
+            + (0.00851 * 16.0) * triangle_wave
+            + (0.00494 * 16.0) * noise
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 44100.0;
+    const MODES: [SoundMode; 2] = [SoundMode::Simple, SoundMode::Accurate];
+
+    fn chip(mode: SoundMode) -> SoundChip {
+        SoundChip {
+            mode,
+            ..SoundChip::default()
+        }
+    }
+
+    // Starts a note at full volume on the channel whose registers start at base
+    fn play(sound: &mut SoundChip, base: u8, flags: u8, sweep: u8, period: u16) {
+        sound.write(base, flags | 0b0000_1111).ok();
+        sound.write(base + 1, sweep).ok();
+        sound.write(base + 2, period as u8).ok();
+        sound.write(base + 3, (period >> 8) as u8 | 0b1000).ok();
+    }
+
+    // Peak output over the given time
+    fn loudest(sound: &mut SoundChip, seconds: f64) -> f32 {
+        (0..(seconds * SAMPLE_RATE) as usize)
+            .map(|_| sound.generate(SAMPLE_RATE))
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn triangles_have_no_sweep_to_mute_them() {
+        for mode in MODES {
+            let mut sound = chip(mode);
+            play(&mut sound, 0xA8, 0b0010_0000, 0x00, 0x400);
+
+            assert!(loudest(&mut sound, 0.1) > 0.0);
+            assert!(!sound.triangle_wave.registers().is_muted());
+        }
+    }
+
+    #[test]
+    fn pulses_mute_when_the_sweep_would_overflow() {
+        for mode in MODES {
+            let mut sound = chip(mode);
+            play(&mut sound, 0xA0, 0b1010_0000, 0x00, 0x400);
+
+            assert_eq!(loudest(&mut sound, 0.1), 0.0);
+            assert!(sound.square_wave_1.registers().is_muted());
+        }
+    }
+
+    #[test]
+    fn notes_end_after_their_length() {
+        for mode in MODES {
+            let mut sound = chip(mode);
+            play(&mut sound, 0xA0, 0b1000_0000, 0x00, 0x100);
+
+            assert!(loudest(&mut sound, 0.01) > 0.0);
+            loudest(&mut sound, 2.5);
+            assert_eq!(loudest(&mut sound, 0.1), 0.0);
+        }
+    }
+
+    #[test]
+    fn looping_holds_notes() {
+        for mode in MODES {
+            let mut sound = chip(mode);
+            play(&mut sound, 0xA0, 0b1010_0000, 0x00, 0x100);
+            loudest(&mut sound, 2.5);
+
+            assert!(loudest(&mut sound, 0.1) > 0.0);
+        }
+    }
+
+    #[test]
+    fn looping_envelopes_restart() {
+        for mode in MODES {
+            // The envelope takes a second to decay
+            let mut sound = chip(mode);
+            play(&mut sound, 0xA0, 0b1011_0000, 0x00, 0x100);
+
+            loudest(&mut sound, 0.8);
+            let decayed = loudest(&mut sound, 0.1);
+            loudest(&mut sound, 0.2);
+            let restarted = loudest(&mut sound, 0.1);
+
+            assert!(restarted > decayed);
+        }
+    }
+
+    #[test]
+    fn sweeps_only_move_the_pulses() {
+        for mode in MODES {
+            let mut sound = chip(mode);
+            // Enabled, period >> 1 per step, down in pitch, as fast as possible
+            play(&mut sound, 0xA0, 0b1010_0000, 0b1001_0000, 0x100);
+            play(&mut sound, 0xA8, 0b0010_0000, 0b1001_0000, 0x100);
+            loudest(&mut sound, 0.1);
+
+            assert!(sound.square_wave_1.registers().swept_period() > 0x100);
+            assert_eq!(sound.triangle_wave.registers().swept_period(), 0x100);
+        }
+    }
+}
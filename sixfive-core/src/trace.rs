@@ -0,0 +1,80 @@
+use crossbeam::queue::ArrayQueue;
+
+// Enough for a few seconds of execution at typical clock speeds
+const TRACE_LENGTH: usize = 4096;
+
+#[derive(Clone, Copy)]
+pub enum TraceEvent {
+    // Logged as each instruction is decoded, with the registers as they were before it ran
+    Instruction {
+        instruction_pointer: u8,
+        rom_bank: usize,
+        opcode: u8,
+        operand: u8,
+        accumulator: u8,
+        status_register: u8,
+    },
+    Write {
+        address: u8,
+        value: u8,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub struct TraceEntry {
+    // In samples since the plugin was loaded
+    pub timestamp: u64,
+    pub event: TraceEvent,
+}
+
+impl TraceEntry {
+    pub fn format(&self) -> String {
+        match self.event {
+            TraceEvent::Instruction {
+                instruction_pointer,
+                rom_bank,
+                opcode,
+                operand,
+                accumulator,
+                status_register,
+            } => format!(
+                "{:>10}  {}:{:02X}  {:02X} {:02X}  A={:02X} P={:08b}",
+                self.timestamp,
+                ["A", "B", "C", "D"][rom_bank],
+                instruction_pointer,
+                opcode,
+                operand,
+                accumulator,
+                status_register,
+            ),
+            TraceEvent::Write { address, value } => format!(
+                "{:>10}          {:02X} -> {:02X}",
+                self.timestamp, value, address
+            ),
+        }
+    }
+}
+
+// Fixed-capacity ring buffer shared between the CPU (on the audio thread) and the editor.
+// Logging never allocates: once full, the oldest entries are overwritten.
+pub struct TraceBuffer {
+    entries: ArrayQueue<TraceEntry>,
+}
+
+impl Default for TraceBuffer {
+    fn default() -> Self {
+        Self {
+            entries: ArrayQueue::new(TRACE_LENGTH),
+        }
+    }
+}
+
+impl TraceBuffer {
+    pub fn log(&self, timestamp: u64, event: TraceEvent) {
+        self.entries.force_push(TraceEntry { timestamp, event });
+    }
+
+    pub fn drain(&self) -> impl Iterator<Item = TraceEntry> + '_ {
+        std::iter::from_fn(|| self.entries.pop())
+    }
+}
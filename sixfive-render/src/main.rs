@@ -0,0 +1,528 @@
+// Renders a program to a WAV file without a DAW, for stems and regression checks:
+//
+//     sixfive-render patches/fib.txt -o fib.wav --seconds 10
+//
+// The input is hex words (as in patches/*.txt), assembly source (.asm) or a saved plugin
+// state (JSON).
+
+use serde_json::Value;
+use std::{env, fs, path::PathBuf, process};
+
+use sixfive_core::{
+    assembler,
+    scheduler::{Scheduler, Transport},
+    sound::{OscillatorQuality, SoundMode},
+    Machine, Rom,
+};
+
+const USAGE: &str = "\
+Usage: sixfive-render <input> [options]
+
+<input> is a file of hex instruction words (like patches/*.txt), assembly source (.asm),
+or a saved plugin state
+
+Options:
+  -o, --output <file>      WAV file to write (default: the input with a .wav extension)
+  --bank <A-D>             Bank to load the program into and start in (default: A)
+  --sample-rate <hz>       Sample rate to render at (default: 44100)
+  --clock <hz>             Instructions per second (default: from the plugin state, or 10)
+  --tempo <bpm>            Tempo for BEAT and tempo-synced clocks (default: 120)
+  --seconds <seconds>      Longest render, if the program doesn't halt (default: 10)
+  --tail <seconds>         How long to keep rendering after the program halts (default: 1)
+  --band-limited           Use band-limited oscillators instead of the naive waveforms
+                           (default: from the plugin state, or naive)
+  --accurate               Clock the sound chip like the NES, with a frame sequencer
+                           (default: from the plugin state, or simple)";
+
+const DEFAULT_CLOCK_SPEED: f64 = 10.0;
+
+// Rendered in blocks like a host would, so the instruction budget applies the same way
+const BUFFER_SIZE: usize = 512;
+
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    bank: Option<usize>,
+    sample_rate: u32,
+    clock_speed: Option<f64>,
+    tempo: f64,
+    seconds: f64,
+    tail: f64,
+    quality: Option<OscillatorQuality>,
+    mode: Option<SoundMode>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut bank = None;
+    let mut sample_rate = 44100;
+    let mut clock_speed = None;
+    let mut tempo = 120.0;
+    let mut seconds = 10.0;
+    let mut tail = 1.0;
+    let mut quality = None;
+    let mut mode = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--bank" => {
+                bank = Some(match value()?.to_uppercase().as_str() {
+                    "A" => 0,
+                    "B" => 1,
+                    "C" => 2,
+                    "D" => 3,
+                    other => return Err(format!("unknown bank {}", other)),
+                })
+            }
+            "--sample-rate" => sample_rate = parse_number(&arg, &value()?)?,
+            "--clock" => clock_speed = Some(parse_number(&arg, &value()?)?),
+            "--tempo" => tempo = parse_number(&arg, &value()?)?,
+            "--seconds" => seconds = parse_number(&arg, &value()?)?,
+            "--tail" => tail = parse_number(&arg, &value()?)?,
+            "--band-limited" => quality = Some(OscillatorQuality::BandLimited),
+            "--accurate" => mode = Some(SoundMode::Accurate),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => input = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let input = input.ok_or("no input file given")?;
+    let output = output.unwrap_or_else(|| input.with_extension("wav"));
+
+    if clock_speed.is_some_and(|clock_speed| clock_speed <= 0.0) || sample_rate == 0 {
+        return Err("clock speed and sample rate must be positive".to_string());
+    }
+
+    Ok(Options {
+        input,
+        output,
+        bank,
+        sample_rate,
+        clock_speed,
+        tempo,
+        seconds,
+        tail,
+        quality,
+        mode,
+    })
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", option, value))
+}
+
+// Rows that start with an address ("0x10: 1190 2191 ...") load from that address
+fn parse_address(line: &str) -> Option<(u8, &str)> {
+    let (address, rest) = line.trim().strip_prefix("0x")?.split_once(':')?;
+    Some((u8::from_str_radix(address, 16).ok()?, rest))
+}
+
+fn parse_hex(text: &str) -> Result<[u16; 0x40], String> {
+    let mut bank = [0; 0x40];
+
+    // Files with address rows (like patches/fib.txt) only load those rows, since the rest
+    // of the file is usually the same program written out again with comments
+    let addressed = text
+        .lines()
+        .any(|line| parse_address(line.split(';').next().unwrap()).is_some());
+
+    let mut index = 0;
+    for (number, line) in text.lines().enumerate() {
+        // Everything after a semicolon is a comment
+        let line = line.split(';').next().unwrap();
+
+        let words = match parse_address(line) {
+            Some((address, rest)) => {
+                if address & 0b1 != 0 || address >= 0x80 {
+                    return Err(format!(
+                        "line {}: bad address 0x{:02X}",
+                        number + 1,
+                        address
+                    ));
+                }
+                index = address as usize / 2;
+                rest
+            }
+            None if addressed => continue,
+            None => line,
+        };
+
+        // Words come first, anything after them (like a mnemonic) is ignored
+        for token in words.split_whitespace() {
+            let word = match u16::from_str_radix(token, 16) {
+                Ok(word) if token.len() == 4 => word,
+                _ => break,
+            };
+
+            if index >= bank.len() {
+                return Err(format!("line {}: program doesn't fit in ROM", number + 1));
+            }
+
+            bank[index] = word;
+            index += 1;
+        }
+    }
+
+    Ok(bank)
+}
+
+// Everything needed to play a program the way the plugin would
+struct Program {
+    rom: Rom,
+    clock_speed: f64,
+    quality: OscillatorQuality,
+    mode: SoundMode,
+}
+
+// Loads ROM (and any settings that aren't overridden) from a plugin state saved by the host
+fn parse_state(text: &str, options: &Options) -> Result<Program, String> {
+    let state: Value =
+        serde_json::from_str(text).map_err(|err| format!("invalid plugin state: {}", err))?;
+
+    // Persisted fields are stored as JSON strings within the state
+    let banks = match &state["fields"]["rom-banks"] {
+        Value::String(banks) => serde_json::from_str(banks).map_err(|err| err.to_string())?,
+        Value::Null => return Err("plugin state has no ROM banks".to_string()),
+        banks => banks.clone(),
+    };
+    let banks: Vec<Vec<u16>> =
+        serde_json::from_value(banks).map_err(|err| format!("invalid ROM banks: {}", err))?;
+
+    let param = |id: &str| {
+        let value = &state["params"][id];
+        value["I32"]
+            .as_i64()
+            .or_else(|| value["Bool"].as_bool().map(i64::from))
+    };
+
+    let mut rom = Rom::default();
+    for (bank, words) in banks.iter().take(rom.banks.len()).enumerate() {
+        rom.load_bank(bank, words);
+    }
+    for (i, vector) in rom.trampoline_vectors.iter_mut().enumerate() {
+        *vector = param(&format!("state_{}", i + 1)).unwrap_or(0) as u8;
+    }
+    rom.power_on_bank = options
+        .bank
+        .or_else(|| param("rom-bank-select").map(|bank| bank as usize & 0b11))
+        .unwrap_or(0);
+
+    // Same as SixFiveParams::clock_frequency
+    let clock_ticks = param("clock-ticks").unwrap_or(4) as f64;
+    let clock_speed = options.clock_speed.unwrap_or(match param("clock-mode") {
+        Some(1) => clock_ticks * options.tempo / 60.0,
+        Some(2) => clock_ticks * 4.0 * options.tempo / 60.0,
+        _ => param("clock-speed").map_or(DEFAULT_CLOCK_SPEED, |speed| speed as f64),
+    });
+
+    // Same as Quality::oscillator_quality and Emulation::sound_mode
+    let quality = options.quality.unwrap_or(match param("quality") {
+        Some(1) => OscillatorQuality::BandLimited,
+        _ => OscillatorQuality::Naive,
+    });
+    let mode = options.mode.unwrap_or(match param("emulation") {
+        Some(1) => SoundMode::Accurate,
+        _ => SoundMode::Simple,
+    });
+
+    Ok(Program {
+        rom,
+        clock_speed,
+        quality,
+        mode,
+    })
+}
+
+// Runs the machine the same way the plugin does, minus the host
+fn render(machine: &mut Machine, clock_speed: f64, options: &Options) -> Vec<f32> {
+    let sample_rate = options.sample_rate as f64;
+
+    let max_samples = (options.seconds * sample_rate) as usize;
+    let tail_samples = (options.tail * sample_rate) as usize;
+
+    let mut samples = Vec::with_capacity(max_samples);
+    let mut scheduler = Scheduler::default();
+    let mut halted_at = None;
+
+    machine.clock_running = true;
+
+    while samples.len() < max_samples {
+        scheduler.begin_buffer(
+            BUFFER_SIZE,
+            sample_rate,
+            clock_speed,
+            Transport {
+                tempo: options.tempo,
+                playing: true,
+                position: None,
+            },
+        );
+
+        for _ in 0..BUFFER_SIZE.min(max_samples - samples.len()) {
+            samples.push(scheduler.process_sample(machine));
+
+            if !machine.clock_running {
+                let halted_at = *halted_at.get_or_insert(samples.len());
+                if samples.len() >= halted_at + tail_samples {
+                    return samples;
+                }
+            }
+        }
+    }
+
+    samples
+}
+
+// 16-bit mono PCM
+fn write_wav(path: &PathBuf, samples: &[f32], sample_rate: u32) -> std::io::Result<()> {
+    let data_length = samples.len() as u32 * 2;
+
+    let mut bytes = Vec::with_capacity(44 + data_length as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_length).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // channels
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // bytes per second
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // bytes per frame
+    bytes.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_length.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fs::write(path, bytes)
+}
+
+// Works out from the input whether it's a plugin state, assembly source or hex words
+fn load_program(text: &str, options: &Options) -> Result<Program, String> {
+    if text.trim_start().starts_with('{') {
+        parse_state(text, options)
+    } else {
+        let bank = options.bank.unwrap_or(0);
+
+        let mut rom = Rom::default();
+        if options
+            .input
+            .extension()
+            .is_some_and(|extension| extension == "asm")
+        {
+            let words = assembler::assemble(text).map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                errors.join("\n")
+            })?;
+            rom.banks[bank].copy_from_slice(&words);
+        } else {
+            rom.banks[bank] = parse_hex(text)?;
+        }
+        rom.power_on_bank = bank;
+
+        Ok(Program {
+            rom,
+            clock_speed: options.clock_speed.unwrap_or(DEFAULT_CLOCK_SPEED),
+            quality: options.quality.unwrap_or(OscillatorQuality::Naive),
+            mode: options.mode.unwrap_or(SoundMode::Simple),
+        })
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let text = fs::read_to_string(&options.input)
+        .map_err(|err| format!("couldn't read {}: {}", options.input.display(), err))?;
+    let program = load_program(&text, options)?;
+
+    let mut machine = Machine::new(program.rom);
+    machine.sound.quality = program.quality;
+    machine.sound.mode = program.mode;
+    let samples = render(&mut machine, program.clock_speed, options);
+
+    write_wav(&options.output, &samples, options.sample_rate)
+        .map_err(|err| format!("couldn't write {}: {}", options.output.display(), err))?;
+
+    println!(
+        "Wrote {:.2}s to {}",
+        samples.len() as f64 / options.sample_rate as f64,
+        options.output.display()
+    );
+
+    // A fault stops the program, which CI should treat as a failure
+    if let Some(fault) = machine.fault {
+        return Err(format!(
+            "{} at 0x{:02X} in bank {} ({:04X})",
+            fault.fault.describe(),
+            fault.instruction_pointer,
+            ["A", "B", "C", "D"][fault.rom_bank],
+            fault.instruction,
+        ));
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = parse_args(env::args().skip(1)).and_then(|options| run(&options)) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        parse_args(args.iter().map(ToString::to_string)).unwrap()
+    }
+
+    fn patch(name: &str) -> String {
+        format!("{}/../patches/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn read_wav(path: &PathBuf) -> (Vec<u8>, Vec<i16>) {
+        let bytes = fs::read(path).unwrap();
+        let samples = bytes[44..]
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        (bytes, samples)
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = options(&["song.asm", "--bank", "c", "--clock", "100", "--accurate"]);
+        assert_eq!(options.output, PathBuf::from("song.wav"));
+        assert_eq!(options.bank, Some(2));
+        assert_eq!(options.clock_speed, Some(100.0));
+        assert!(options.mode == Some(SoundMode::Accurate));
+        assert!(options.quality.is_none());
+
+        for args in [
+            &["song.asm", "--bank", "E"][..],
+            &["song.asm", "--clock", "0"],
+            &["--tail"],
+        ] {
+            assert!(parse_args(args.iter().map(ToString::to_string)).is_err());
+        }
+    }
+
+    #[test]
+    fn hex_files_only_load_address_rows() {
+        let text = fs::read_to_string(patch("fib.txt")).unwrap();
+        let program = load_program(&text, &options(&["fib.txt"])).unwrap();
+
+        assert_eq!(program.rom.banks[0][..3], [0x1080, 0x129F, 0x1001]);
+        // 0x10, rather than the commented listing that follows the rows
+        assert_eq!(program.rom.banks[0][8], 0x1190);
+        assert_eq!(program.clock_speed, DEFAULT_CLOCK_SPEED);
+    }
+
+    #[test]
+    fn assembly_is_assembled_into_the_chosen_bank() {
+        let options = options(&["song.asm", "--bank", "B"]);
+        let program = load_program("LOAD #$2A\nHALT", &options).unwrap();
+
+        assert_eq!(program.rom.banks[1][..2], [0x102A, 0x0000]);
+        assert_eq!(program.rom.power_on_bank, 1);
+
+        let errors = load_program("LOAD", &options).err().unwrap();
+        assert!(errors.contains("LOAD"));
+    }
+
+    #[test]
+    fn plugin_states_bring_their_settings() {
+        let state = r#"{
+            "params": {
+                "clock-speed": { "I32": 100 },
+                "rom-bank-select": { "I32": 1 },
+                "quality": { "I32": 1 },
+                "emulation": { "I32": 1 }
+            },
+            "fields": { "rom-banks": "[[], [4138, 0]]" }
+        }"#;
+        let program = load_program(state, &options(&["state.json"])).unwrap();
+
+        assert_eq!(program.rom.banks[1][..2], [0x102A, 0x0000]);
+        assert_eq!(program.rom.power_on_bank, 1);
+        assert_eq!(program.clock_speed, 100.0);
+        assert!(program.quality == OscillatorQuality::BandLimited);
+        assert!(program.mode == SoundMode::Accurate);
+
+        // Options on the command line win
+        let program = load_program(state, &options(&["state.json", "--clock", "5"])).unwrap();
+        assert_eq!(program.clock_speed, 5.0);
+    }
+
+    #[test]
+    fn renders_fib_to_a_wav() {
+        let output = env::temp_dir().join("sixfive-render-fib.wav");
+        let fib = patch("fib.txt");
+        let options = options(&[&fib, "-o", output.to_str().unwrap(), "--clock", "1000"]);
+        run(&options).unwrap();
+
+        let (bytes, samples) = read_wav(&output);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize,
+            bytes.len() - 8
+        );
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([bytes[22], bytes[23]]), 1);
+        assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), 44100);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(
+            u32::from_le_bytes(bytes[40..44].try_into().unwrap()) as usize,
+            samples.len() * 2
+        );
+
+        // fib halts once the numbers are written, and the render stops a second later
+        assert!(samples.len() < 44100 * 2);
+    }
+
+    #[test]
+    fn renders_are_not_silent() {
+        let output = env::temp_dir().join("sixfive-render-channeltests.wav");
+        let channel_tests = patch("channeltests.txt");
+        let options = options(&[
+            &channel_tests,
+            "-o",
+            output.to_str().unwrap(),
+            "--seconds",
+            "1",
+        ]);
+        run(&options).unwrap();
+
+        let (_, samples) = read_wav(&output);
+        assert_eq!(samples.len(), 44100);
+        assert!(samples.iter().any(|sample| sample.unsigned_abs() > 1000));
+    }
+
+    #[test]
+    fn faults_fail_the_render() {
+        let input = env::temp_dir().join("sixfive-render-fault.txt");
+        fs::write(&input, "1080 1200 ; LOAD #$80, STOR $00").unwrap();
+        let options = options(&[input.to_str().unwrap(), "--clock", "1000"]);
+
+        let err = run(&options).err().unwrap();
+        assert_eq!(err, "Write to read-only 0x00 at 0x02 in bank A (1200)");
+        // The audio up to the fault is still written
+        assert!(options.output.exists());
+    }
+}
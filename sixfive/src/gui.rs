@@ -1,49 +1,119 @@
 use nih_plug::prelude::*;
 use nih_plug_egui::{create_egui_editor, egui, EguiState};
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use sixfive_core::{
+    assembler::{self, AssemblerError},
+    debug::{Breakpoint, Watchpoint},
+    isa,
+    sound::{self, conversions, ChannelRegisters},
+    trace::{TraceBuffer, TraceEntry, TraceEvent},
+};
 
 use crate::{
-    cpu::Cpu,
-    params::{RomBank, SixFiveParams},
-    sound::{conversions, ChannelRegisters},
+    params::{ClockMode, Emulation, Quality, RomBank, SixFiveParams},
+    shared::{CommandQueue, CpuSnapshot, EditorCommand},
 };
 
 const OVERWRITE_INSTRUCTION_POINTER_VALUES: [u8; 8] =
     [0x00, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70];
 const TRAMPOLINE_VECTOR_JUMP_ADDRESSES: [(u8, u8); 4] =
     [(0x28, 0x2C), (0x28, 0x3C), (0x30, 0x34), (0x38, 0x3C)];
+const TRACE_HISTORY_LENGTH: usize = 10_000;
+
+struct TraceView {
+    open: bool,
+    // Entries are formatted once when they arrive, rather than every frame
+    entries: VecDeque<(TraceEntry, String)>,
+    show_instructions: bool,
+    show_writes: bool,
+    filter: String,
+    export_path: String,
+    export_status: String,
+}
+
+impl Default for TraceView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            entries: VecDeque::new(),
+            show_instructions: true,
+            show_writes: true,
+            filter: String::new(),
+            export_path: "sixfive-trace.txt".to_string(),
+            export_status: String::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct AssemblyView {
+    open: bool,
+    // Errors from checking the source, and which bank they're for
+    errors: Vec<AssemblerError>,
+    checked_bank: Option<usize>,
+    status: String,
+}
 
 struct GuiUserState {
     rom_bank: Vec<String>,
     clock_speed: String,
+    // Accumulator value (in hex) for conditional breakpoints
+    breakpoint_condition: String,
+    watch_address: String,
+    watchpoint: Watchpoint,
+    show_disassembly: bool,
+    assembly: AssemblyView,
+    trace: TraceView,
 }
 
 impl GuiUserState {
     fn new(params: &Arc<SixFiveParams>) -> Self {
-        let mut rom_bank = Vec::new();
-        for i in 0x00..0x40 {
-            let high = params.read_rom(i * 2) as u16;
-            let low = params.read_rom(i * 2 + 1) as u16;
-            rom_bank.push(format!("{:04X}", (high << 8) | low));
-        }
+        let bank_index = params.rom_bank_select.value().as_index();
+        let rom_bank = params.rom_banks.lock().unwrap()[bank_index]
+            .iter()
+            .map(|b| format!("{:04X}", b))
+            .collect();
 
         Self {
             rom_bank,
-            clock_speed: params.clock_speed.value().to_string(),
+            clock_speed: match params.clock_mode.value() {
+                ClockMode::Hertz => params.clock_speed.value(),
+                ClockMode::TicksPerBeat | ClockMode::TicksPerSixteenth => {
+                    params.clock_ticks.value()
+                }
+            }
+            .to_string(),
+            breakpoint_condition: "00".to_string(),
+            watch_address: "80".to_string(),
+            watchpoint: Watchpoint {
+                read: false,
+                write: true,
+            },
+            show_disassembly: false,
+            assembly: AssemblyView::default(),
+            trace: TraceView::default(),
         }
     }
 }
 
-fn draw_rom_location(ui: &mut egui::Ui, active: bool, input: &mut String, value: &mut u16) {
+fn draw_rom_location(
+    ui: &mut egui::Ui,
+    outline: egui::Color32,
+    breakpoint: Option<Breakpoint>,
+    input: &mut String,
+    value: &mut u16,
+) -> egui::Response {
     egui::Frame::none()
-        .stroke(egui::Stroke::new(
-            2.0,
-            if active {
-                egui::Color32::RED
-            } else {
-                egui::Color32::BLACK
-            },
-        ))
+        .fill(match breakpoint {
+            Some(Breakpoint::Always) => egui::Color32::from_rgb(255, 180, 180),
+            Some(Breakpoint::AccumulatorEquals(_)) => egui::Color32::from_rgb(255, 220, 160),
+            None => egui::Color32::TRANSPARENT,
+        })
+        .stroke(egui::Stroke::new(2.0, outline))
         .show(ui, |ui| {
             let response = ui.add(
                 egui::TextEdit::singleline(input)
@@ -65,7 +135,20 @@ fn draw_rom_location(ui: &mut egui::Ui, active: bool, input: &mut String, value:
 
                 *input = format!("{:04X}", value);
             }
-        });
+
+            response
+        })
+        .inner
+}
+
+// Sends a bank to the audio thread's copy of the ROM, after it's been changed in the parameters
+fn publish_rom_bank(commands: &CommandQueue, bank: usize, words: &[u16]) {
+    let mut rom_bank = [0; 0x40];
+    for (word, value) in rom_bank.iter_mut().zip(words) {
+        *word = *value;
+    }
+
+    commands.send(EditorCommand::SetRomBank(bank, rom_bank));
 }
 
 fn draw_rom(
@@ -73,7 +156,8 @@ fn draw_rom(
     params: &SixFiveParams,
     setter: &ParamSetter,
     state: &mut GuiUserState,
-    cpu: &Cpu,
+    cpu: &CpuSnapshot,
+    commands: &CommandQueue,
 ) {
     ui.group(|ui| {
         ui.vertical_centered_justified(|ui| {
@@ -102,10 +186,33 @@ fn draw_rom(
                         .map(|b| format!("{:04X}", b))
                         .collect();
                 }
+
+                ui.add_space(20.0);
+
+                ui.label("Running: ");
+                ui.label(
+                    egui::RichText::from(["A", "B", "C", "D"][cpu.rom_bank])
+                        .monospace()
+                        .color(if cpu.rom_bank == selected_index {
+                            egui::Color32::BLACK
+                        } else {
+                            egui::Color32::RED
+                        }),
+                );
+
+                ui.add_space(20.0);
+
+                ui.toggle_value(&mut state.show_disassembly, "Disassembly");
+                ui.toggle_value(&mut state.assembly.open, "Assembly");
             });
 
             ui.separator();
 
+            // Locked once for the whole grid, the audio thread has its own copy
+            let mut rom_banks = params.rom_banks.lock().unwrap();
+            let words = &mut rom_banks[selected_index];
+            let mut changed = false;
+
             for row in 0..8 {
                 ui.horizontal_top(|ui| {
                     ui.label(egui::RichText::from(format!("0x{:02X}", row * 16)).monospace());
@@ -114,21 +221,322 @@ fn draw_rom(
 
                     for col in 0..8 {
                         let index = row * 8 + col;
+                        let address = index as u8 * 2;
+                        let breakpoint = cpu.breakpoints.get(selected_index, address);
+
+                        let watch_hit = cpu.watch_hit.is_some_and(|hit| {
+                            hit.rom_bank == selected_index && hit.instruction_pointer == address
+                        });
 
-                        draw_rom_location(
+                        let previous = words[index];
+                        let mut response = draw_rom_location(
                             ui,
-                            cpu.instruction_pointer == (index as u8 * 2),
+                            if cpu.rom_bank == selected_index && cpu.instruction_pointer == address
+                            {
+                                egui::Color32::RED
+                            } else if watch_hit {
+                                // The instruction that tripped a watchpoint
+                                egui::Color32::BLUE
+                            } else {
+                                egui::Color32::BLACK
+                            },
+                            breakpoint,
                             &mut state.rom_bank[index],
-                            &mut params.rom_banks.lock().unwrap()[selected_index][index],
-                        )
+                            &mut words[index],
+                        );
+                        changed |= words[index] != previous;
+
+                        if let Some(Breakpoint::AccumulatorEquals(value)) = breakpoint {
+                            response =
+                                response.on_hover_text(format!("Break when A = 0x{:02X}", value));
+                        }
+
+                        // Right-click a location to set breakpoints on it
+                        response.context_menu(|ui| {
+                            if ui
+                                .button(if breakpoint.is_some() {
+                                    "Remove Breakpoint"
+                                } else {
+                                    "Add Breakpoint"
+                                })
+                                .clicked()
+                            {
+                                commands
+                                    .send(EditorCommand::ToggleBreakpoint(selected_index, address));
+                                ui.close_menu();
+                            }
+
+                            ui.horizontal(|ui| {
+                                ui.label("Break when A = 0x");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut state.breakpoint_condition)
+                                        .font(egui::TextStyle::Monospace)
+                                        .desired_width(20.0),
+                                );
+
+                                if ui.button("Set").clicked() {
+                                    if let Ok(value) =
+                                        u8::from_str_radix(&state.breakpoint_condition, 16)
+                                    {
+                                        commands.send(EditorCommand::SetBreakpoint(
+                                            selected_index,
+                                            address,
+                                            Some(Breakpoint::AccumulatorEquals(value)),
+                                        ));
+                                    }
+                                    ui.close_menu();
+                                }
+                            });
+
+                            if ui
+                                .add_enabled(cpu.fault.is_none(), egui::Button::new("Run to Here"))
+                                .clicked()
+                            {
+                                commands.send(EditorCommand::RunTo(selected_index, address));
+                                ui.close_menu();
+                            }
+                        });
                     }
                 });
             }
+
+            if changed {
+                publish_rom_bank(commands, selected_index, words);
+            }
+        });
+    });
+}
+
+fn draw_disassembly(
+    egui_ctx: &egui::Context,
+    params: &SixFiveParams,
+    cpu: &CpuSnapshot,
+    open: &mut bool,
+) {
+    let bank = params.rom_bank_select.value().as_index();
+    let words = params.rom_banks.lock().unwrap()[bank].clone();
+
+    egui::Window::new(format!("Disassembly (Bank {})", ["A", "B", "C", "D"][bank]))
+        .id(egui::Id::new("disassembly"))
+        .open(open)
+        .default_size([360.0, 400.0])
+        .show(egui_ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    egui::Grid::new("disassembly-grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for (index, word) in words.iter().enumerate() {
+                                let address = index as u8 * 2;
+                                let current =
+                                    cpu.rom_bank == bank && cpu.instruction_pointer == address;
+
+                                let color = if current {
+                                    egui::Color32::RED
+                                } else {
+                                    egui::Color32::BLACK
+                                };
+
+                                ui.label(
+                                    egui::RichText::from(format!("0x{:02X}", address))
+                                        .monospace()
+                                        .color(color),
+                                );
+                                ui.label(
+                                    egui::RichText::from(format!("{:04X}", word))
+                                        .monospace()
+                                        .color(color),
+                                );
+
+                                match isa::disassemble(*word) {
+                                    Some(text) => ui
+                                        .label(egui::RichText::from(text).monospace().color(color)),
+                                    None => ui
+                                        .label(
+                                            egui::RichText::from("???")
+                                                .monospace()
+                                                .color(egui::Color32::GRAY),
+                                        )
+                                        .on_hover_text("Illegal opcode"),
+                                };
+
+                                match isa::written_address(*word).and_then(sound::describe_register)
+                                {
+                                    Some(description) => ui.label(
+                                        egui::RichText::from(description)
+                                            .small()
+                                            .color(egui::Color32::BLUE),
+                                    ),
+                                    None => ui.label(""),
+                                };
+
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+}
+
+fn draw_assembly(
+    egui_ctx: &egui::Context,
+    params: &SixFiveParams,
+    state: &mut GuiUserState,
+    commands: &CommandQueue,
+) {
+    let bank = params.rom_bank_select.value().as_index();
+    let view = &mut state.assembly;
+    let mut open = view.open;
+
+    egui::Window::new(format!("Assembly (Bank {})", ["A", "B", "C", "D"][bank]))
+        .id(egui::Id::new("assembly"))
+        .open(&mut open)
+        .default_size([360.0, 400.0])
+        .show(egui_ctx, |ui| {
+            let mut sources = params.rom_sources.lock().unwrap();
+            let source = &mut sources[bank];
+
+            ui.horizontal(|ui| {
+                let can_assemble = view.errors.is_empty() && !source.trim().is_empty();
+
+                if ui
+                    .add_enabled(can_assemble, egui::Button::new("Assemble"))
+                    .on_hover_text("Replace this bank's ROM with the assembled program")
+                    .clicked()
+                {
+                    if let Ok(words) = assembler::assemble(source) {
+                        state.rom_bank = words.iter().map(|b| format!("{:04X}", b)).collect();
+                        publish_rom_bank(commands, bank, &words);
+                        params.rom_banks.lock().unwrap()[bank] = words;
+                        view.status = "Assembled to ROM".to_string();
+                    }
+                }
+
+                ui.label(&view.status);
+            });
+
+            ui.separator();
+
+            // Lines with errors are highlighted as they're typed
+            let error_lines: Vec<usize> = view.errors.iter().map(|error| error.line).collect();
+            let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+
+                let mut job = egui::text::LayoutJob::default();
+                for (number, line) in text.split_inclusive('\n').enumerate() {
+                    job.append(
+                        line,
+                        0.0,
+                        egui::TextFormat {
+                            font_id: font_id.clone(),
+                            color: egui::Color32::BLACK,
+                            background: if error_lines.contains(&(number + 1)) {
+                                egui::Color32::from_rgb(255, 200, 200)
+                            } else {
+                                egui::Color32::TRANSPARENT
+                            },
+                            ..Default::default()
+                        },
+                    );
+                }
+                job.wrap.max_width = wrap_width;
+
+                ui.fonts().layout_job(job)
+            };
+
+            let mut changed = false;
+            egui::ScrollArea::vertical()
+                .max_height(ui.available_height() - 80.0)
+                .show(ui, |ui| {
+                    changed = ui
+                        .add(
+                            egui::TextEdit::multiline(source)
+                                .code_editor()
+                                .desired_rows(20)
+                                .desired_width(f32::INFINITY)
+                                .layouter(&mut layouter),
+                        )
+                        .changed();
+                });
+
+            if changed || view.checked_bank != Some(bank) {
+                view.errors = assembler::assemble(source).err().unwrap_or_default();
+                view.checked_bank = Some(bank);
+                view.status.clear();
+            }
+
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .id_source("assembly-errors")
+                .show(ui, |ui| {
+                    for error in &view.errors {
+                        ui.label(
+                            egui::RichText::from(error.to_string())
+                                .monospace()
+                                .color(egui::Color32::RED),
+                        );
+                    }
+                });
         });
+
+    view.open = open;
+}
+
+fn draw_memory_location(
+    ui: &mut egui::Ui,
+    cpu: &CpuSnapshot,
+    commands: &CommandQueue,
+    address: u8,
+    value: u8,
+) {
+    let watchpoint = cpu.watchpoints.get(address);
+
+    let response = ui
+        .add(
+            egui::Label::new(
+                egui::RichText::from(format!("{:02X}", value))
+                    .monospace()
+                    .color(if watchpoint.is_set() {
+                        egui::Color32::BLUE
+                    } else {
+                        egui::Color32::BLACK
+                    }),
+            )
+            .sense(egui::Sense::click()),
+        )
+        .on_hover_text(match cpu.last_writers.get(address) {
+            Some((bank, instruction_pointer)) => format!(
+                "0x{:02X}: last written by 0x{:02X} in bank {}",
+                address,
+                instruction_pointer,
+                ["A", "B", "C", "D"][bank]
+            ),
+            None => format!("0x{:02X}: not written since reset", address),
+        });
+
+    // Right-click a location to set watchpoints on it
+    response.context_menu(|ui| {
+        let mut watchpoint = watchpoint;
+
+        let read_changed = ui.checkbox(&mut watchpoint.read, "Break on Read").changed();
+        let write_changed = ui
+            .checkbox(&mut watchpoint.write, "Break on Write")
+            .changed();
+
+        if read_changed || write_changed {
+            commands.send(EditorCommand::SetWatchpoint(address, watchpoint));
+        }
     });
 }
 
-fn draw_ram(ui: &mut egui::Ui, cpu: &Cpu) {
+fn draw_ram(
+    ui: &mut egui::Ui,
+    cpu: &CpuSnapshot,
+    commands: &CommandQueue,
+    watch_address: &mut String,
+    watchpoint: &mut Watchpoint,
+) {
     ui.group(|ui| {
         ui.vertical_centered_justified(|ui| {
             for row in 0..2 {
@@ -140,10 +548,8 @@ fn draw_ram(ui: &mut egui::Ui, cpu: &Cpu) {
                     ui.add_space(15.0);
 
                     for col in 0..16 {
-                        ui.label(
-                            egui::RichText::from(format!("{:02X}", cpu.ram[row * 0x10 + col]))
-                                .monospace(),
-                        );
+                        let index = row * 0x10 + col;
+                        draw_memory_location(ui, cpu, commands, 0x80 + index as u8, cpu.ram[index]);
                     }
 
                     ui.add_space(ui.available_width());
@@ -159,20 +565,48 @@ fn draw_ram(ui: &mut egui::Ui, cpu: &Cpu) {
 
                 ui.add_space(15.0);
 
-                for register in 0xA0..=0xAF {
-                    ui.label(
-                        egui::RichText::from(format!("{:02X}", cpu.sound.read(register)))
-                            .monospace(),
-                    );
+                for (i, register) in cpu.sound_registers.iter().enumerate() {
+                    draw_memory_location(ui, cpu, commands, 0xA0 + i as u8, *register);
                 }
 
                 ui.add_space(ui.available_width());
             });
         });
     });
+
+    // Addresses outside of RAM and the sound chip can be watched from here
+    ui.group(|ui| {
+        ui.horizontal(|ui| {
+            ui.label("Watch 0x");
+            ui.add(
+                egui::TextEdit::singleline(watch_address)
+                    .font(egui::TextStyle::Monospace)
+                    .desired_width(20.0),
+            );
+            ui.checkbox(&mut watchpoint.read, "Read");
+            ui.checkbox(&mut watchpoint.write, "Write");
+
+            if ui.button("Set").clicked() {
+                if let Ok(address) = u8::from_str_radix(watch_address, 16) {
+                    commands.send(EditorCommand::SetWatchpoint(address, *watchpoint));
+                }
+            }
+
+            let watched: Vec<String> = (0x00..=0xFF)
+                .filter(|address| cpu.watchpoints.get(*address).is_set())
+                .map(|address| format!("{:02X}", address))
+                .collect();
+
+            if !watched.is_empty() {
+                ui.label(egui::RichText::from(watched.join(" ")).monospace());
+            }
+
+            ui.add_space(ui.available_width());
+        });
+    });
 }
 
-fn draw_audio_register(ui: &mut egui::Ui, registers: &ChannelRegisters) {
+fn draw_audio_register(ui: &mut egui::Ui, registers: &ChannelRegisters, noise: bool) {
     ui.vertical_centered_justified(|ui| {
         ui.horizontal(|ui| {
             ui.vertical(|ui| {
@@ -242,13 +676,11 @@ fn draw_audio_register(ui: &mut egui::Ui, registers: &ChannelRegisters) {
                 ui.label(
                     egui::RichText::from(format!("{:03b}", registers.shift_period)).monospace(),
                 );
+                // Each step changes the period by period >> shift period
                 ui.label(
-                    egui::RichText::from(format!(
-                        "{:.03}s",
-                        registers.shift_period as f64 * (1.0 / 120.0)
-                    ))
-                    .monospace()
-                    .small(),
+                    egui::RichText::from(format!(">>{}", registers.shift_period))
+                        .monospace()
+                        .small(),
                 );
             });
 
@@ -293,21 +725,61 @@ fn draw_audio_register(ui: &mut egui::Ui, registers: &ChannelRegisters) {
 
         ui.separator();
 
-        ui.label(
-            egui::RichText::from(format!(
-                "🎵 {:.01}Hz",
-                1.0 / conversions::note_period_to_seconds(registers.period)
-            ))
-            .monospace(),
-        );
-        ui.label(
-            egui::RichText::from(format!(
-                "{:03b} {:08b}",
-                (registers.period & 0x0700) >> 8,
-                registers.period & 0x00FF
-            ))
-            .monospace(),
-        );
+        if noise {
+            // Shift rate, and whether the short ("metallic") mode is selected
+            ui.label(
+                egui::RichText::from(format!(
+                    "🎵 {:.01}Hz {}",
+                    1.0 / conversions::noise_period_to_seconds(registers.period),
+                    if registers.noise_short_mode() {
+                        "short"
+                    } else {
+                        "long"
+                    }
+                ))
+                .monospace(),
+            );
+            ui.label(
+                egui::RichText::from(format!(
+                    "{} {:04b}",
+                    registers.noise_short_mode() as u8,
+                    registers.period & 0x000F
+                ))
+                .monospace(),
+            );
+        } else {
+            // The pitch as it's playing, after any sweeping
+            let swept_period = registers.swept_period();
+            ui.label(
+                egui::RichText::from(if registers.is_muted() {
+                    "🔇 Muted".to_string()
+                } else {
+                    format!(
+                        "🎵 {:.01}Hz",
+                        1.0 / conversions::note_period_to_seconds(swept_period)
+                    )
+                })
+                .monospace(),
+            );
+            ui.label(
+                egui::RichText::from(format!(
+                    "{:03b} {:08b}",
+                    (registers.period & 0x0700) >> 8,
+                    registers.period & 0x00FF
+                ))
+                .monospace(),
+            );
+            if registers.shift_enabled {
+                ui.label(
+                    egui::RichText::from(format!(
+                        "↪ {:03b} {:08b}",
+                        (swept_period & 0x0700) >> 8,
+                        swept_period & 0x00FF
+                    ))
+                    .monospace(),
+                );
+            }
+        }
 
         ui.separator();
 
@@ -322,23 +794,23 @@ fn draw_audio_register(ui: &mut egui::Ui, registers: &ChannelRegisters) {
     });
 }
 
-fn draw_audio_registers(ui: &mut egui::Ui, cpu: &Cpu) {
+fn draw_audio_registers(ui: &mut egui::Ui, cpu: &CpuSnapshot) {
     ui.columns(4, |columns| {
         columns[0].group(|ui| {
             ui.label("Square Wave 1");
-            draw_audio_register(ui, cpu.sound.square_wave_1.registers());
+            draw_audio_register(ui, &cpu.square_wave_1, false);
         });
         columns[1].group(|ui| {
             ui.label("Square Wave 2");
-            draw_audio_register(ui, cpu.sound.square_wave_2.registers());
+            draw_audio_register(ui, &cpu.square_wave_2, false);
         });
         columns[2].group(|ui| {
             ui.label("Triangle Wave");
-            draw_audio_register(ui, cpu.sound.triangle_wave.registers());
+            draw_audio_register(ui, &cpu.triangle_wave, false);
         });
         columns[3].group(|ui| {
             ui.label("Noise");
-            draw_audio_register(ui, cpu.sound.noise.registers());
+            draw_audio_register(ui, &cpu.noise, true);
         });
     })
 }
@@ -347,29 +819,52 @@ fn draw_instruction_pointer(
     ui: &mut egui::Ui,
     params: &SixFiveParams,
     setter: &ParamSetter,
-    cpu: &mut Cpu,
+    cpu: &CpuSnapshot,
+    commands: &CommandQueue,
     input: &mut String,
+    show_trace: &mut bool,
 ) {
     ui.group(|ui| {
         ui.vertical_centered_justified(|ui| {
             ui.horizontal_top(|ui| {
                 if ui.button("↺").clicked() {
-                    cpu.reset();
+                    commands.send(EditorCommand::Reset);
                 }
 
-                if ui.button("▶").clicked() {
-                    cpu.clock_running = true;
+                // A faulted CPU has to have the fault cleared (or be reset) before it runs again
+                if ui
+                    .add_enabled(cpu.fault.is_none(), egui::Button::new("▶"))
+                    .clicked()
+                {
+                    commands.send(EditorCommand::Run);
                 }
 
                 if ui.button("⏹").clicked() {
-                    cpu.reset();
-                    cpu.clock_running = false;
+                    commands.send(EditorCommand::Stop);
                 }
 
                 if ui.button("⏸").clicked() {
-                    cpu.clock_running = false;
+                    commands.send(EditorCommand::Pause);
+                }
+
+                if ui
+                    .add_enabled(cpu.fault.is_none(), egui::Button::new("Step"))
+                    .on_hover_text("Execute one instruction")
+                    .clicked()
+                {
+                    commands.send(EditorCommand::Step);
+                }
+
+                if ui
+                    .add_enabled(cpu.fault.is_none(), egui::Button::new("Over"))
+                    .on_hover_text("Execute one instruction, running calls until they return")
+                    .clicked()
+                {
+                    commands.send(EditorCommand::StepOver);
                 }
 
+                ui.toggle_value(show_trace, "📜");
+
                 ui.add_space(20.0);
 
                 ui.label("Instruction Pointer");
@@ -378,6 +873,12 @@ fn draw_instruction_pointer(
                 );
                 ui.label("Clock Speed");
 
+                let clock_mode = params.clock_mode.value();
+                let clock_param = match clock_mode {
+                    ClockMode::Hertz => &params.clock_speed,
+                    ClockMode::TicksPerBeat | ClockMode::TicksPerSixteenth => &params.clock_ticks,
+                };
+
                 egui::Frame::default().show(ui, |ui| {
                     let response = ui.add(
                         egui::TextEdit::singleline(input)
@@ -391,27 +892,143 @@ fn draw_instruction_pointer(
                     }
 
                     if response.lost_focus() {
-                        if ui.input().key_pressed(egui::Key::Enter) {
-                            let value = input.parse().unwrap();
+                        let value = match input.parse() {
+                            Ok(value) if ui.input().key_pressed(egui::Key::Enter) => {
+                                setter.begin_set_parameter(clock_param);
+                                setter.set_parameter(clock_param, value);
+                                setter.end_set_parameter(clock_param);
+                                value
+                            }
+                            // Otherwise (including for anything that isn't a number), put it back
+                            _ => clock_param.value(),
+                        };
+
+                        *input = format!("{}", value);
+                    }
+                });
 
-                            setter.begin_set_parameter(&params.clock_speed);
-                            setter.set_parameter(&params.clock_speed, value);
-                            setter.end_set_parameter(&params.clock_speed);
+                let mut mode_index = clock_mode.as_index();
 
-                            *input = format!("{}", value);
-                        } else {
-                            *input = format!("{}", params.clock_speed.value());
+                if egui::ComboBox::from_id_source("clock-mode")
+                    .width(60.0)
+                    .show_index(ui, &mut mode_index, 3, |i| {
+                        ["Hz", "/ beat", "/ 16th"][i].to_string()
+                    })
+                    .changed()
+                {
+                    let mode = ClockMode::from_index(mode_index).unwrap();
+
+                    setter.begin_set_parameter(&params.clock_mode);
+                    setter.set_parameter(&params.clock_mode, mode);
+                    setter.end_set_parameter(&params.clock_mode);
+
+                    *input = match mode {
+                        ClockMode::Hertz => params.clock_speed.value(),
+                        ClockMode::TicksPerBeat | ClockMode::TicksPerSixteenth => {
+                            params.clock_ticks.value()
                         }
                     }
-                });
+                    .to_string();
+                }
 
                 ui.add_space(ui.available_width());
             });
+
+            if let Some(fault) = cpu.fault {
+                ui.horizontal(|ui| {
+                    ui.label(
+                        egui::RichText::from(format!(
+                            "{} at 0x{:02X} in bank {} ({:04X})",
+                            fault.fault.describe(),
+                            fault.instruction_pointer,
+                            ["A", "B", "C", "D"][fault.rom_bank],
+                            fault.instruction,
+                        ))
+                        .monospace()
+                        .color(egui::Color32::RED),
+                    );
+
+                    if ui.button("Clear Fault").clicked() {
+                        commands.send(EditorCommand::ClearFault);
+                    }
+
+                    ui.add_space(ui.available_width());
+                });
+            }
+
+            if let Some(hit) = cpu.watch_hit {
+                ui.horizontal(|ui| {
+                    ui.label(
+                        egui::RichText::from(format!(
+                            "Watchpoint: {} 0x{:02X} at 0x{:02X} in bank {}",
+                            if hit.write { "write to" } else { "read from" },
+                            hit.address,
+                            hit.instruction_pointer,
+                            ["A", "B", "C", "D"][hit.rom_bank],
+                        ))
+                        .monospace()
+                        .color(egui::Color32::BLUE),
+                    );
+
+                    ui.add_space(ui.available_width());
+                });
+            }
+        });
+    });
+}
+
+fn draw_history(ui: &mut egui::Ui, cpu: &CpuSnapshot, commands: &CommandQueue) {
+    ui.group(|ui| {
+        ui.horizontal(|ui| {
+            ui.label("History");
+
+            let (oldest, newest) = cpu.history_range.unwrap_or((0, 0));
+
+            if ui
+                .add_enabled(
+                    cpu.history_range.is_some() && cpu.instructions_executed > oldest,
+                    egui::Button::new("◀"),
+                )
+                .on_hover_text("Step back")
+                .clicked()
+            {
+                commands.send(EditorCommand::StepBack);
+            }
+
+            // Scrubbing only makes sense while paused, otherwise the history is still moving.
+            // Any instruction can be reached, not just the ones with snapshots.
+            let mut position = cpu.instructions_executed;
+            if ui
+                .add_enabled(
+                    !cpu.clock_running && cpu.history_range.is_some(),
+                    egui::Slider::new(
+                        &mut position,
+                        oldest..=newest.max(cpu.instructions_executed),
+                    ),
+                )
+                .changed()
+            {
+                commands.send(EditorCommand::Seek(position));
+            }
+
+            ui.label("every");
+
+            let mut interval = cpu.history_interval;
+            if ui
+                .add(egui::DragValue::new(&mut interval).clamp_range(1..=4096))
+                .changed()
+            {
+                commands.send(EditorCommand::SetHistoryInterval(interval));
+            }
+
+            ui.label("instructions");
+
+            ui.add_space(ui.available_width());
         });
     });
 }
 
-fn draw_overwrite_instruction_pointer(ui: &mut egui::Ui, cpu: &mut Cpu) {
+fn draw_overwrite_instruction_pointer(ui: &mut egui::Ui, commands: &CommandQueue) {
     ui.group(|ui| {
         ui.label("Overwrite Instruction Pointer");
         for chunk in OVERWRITE_INSTRUCTION_POINTER_VALUES.chunks(4) {
@@ -421,8 +1038,7 @@ fn draw_overwrite_instruction_pointer(ui: &mut egui::Ui, cpu: &mut Cpu) {
                         .button(egui::RichText::from(format!("0x{:02X}", i)).monospace())
                         .clicked()
                     {
-                        cpu.instruction_pointer = i.clone();
-                        cpu.clock_running = true;
+                        commands.send(EditorCommand::SetInstructionPointer(*i));
                     }
                 }
 
@@ -554,11 +1170,58 @@ fn draw_enable_voices(ui: &mut egui::Ui, params: &SixFiveParams, setter: &ParamS
             }
 
             ui.add_space(ui.available_width());
-        })
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Quality");
+
+            let mut quality_index = match params.quality.value() {
+                Quality::Naive => 0,
+                Quality::BandLimited => 1,
+            };
+
+            if egui::ComboBox::from_id_source("quality")
+                .show_index(ui, &mut quality_index, 2, |i| {
+                    ["Naive", "Band-limited"][i].to_string()
+                })
+                .changed()
+            {
+                let quality = [Quality::Naive, Quality::BandLimited][quality_index];
+
+                setter.begin_set_parameter(&params.quality);
+                setter.set_parameter(&params.quality, quality);
+                setter.end_set_parameter(&params.quality);
+            }
+
+            ui.label("Emulation");
+
+            let mut emulation_index = match params.emulation.value() {
+                Emulation::Simple => 0,
+                Emulation::Accurate => 1,
+            };
+
+            if egui::ComboBox::from_id_source("emulation")
+                .show_index(ui, &mut emulation_index, 2, |i| {
+                    ["Simple", "Accurate"][i].to_string()
+                })
+                .on_hover_text(
+                    "Accurate clocks the sound chip like the NES, with a frame sequencer",
+                )
+                .changed()
+            {
+                let emulation = [Emulation::Simple, Emulation::Accurate][emulation_index];
+
+                setter.begin_set_parameter(&params.emulation);
+                setter.set_parameter(&params.emulation, emulation);
+                setter.end_set_parameter(&params.emulation);
+            }
+
+            ui.add_space(ui.available_width());
+        });
     });
 }
 
-fn draw_register_view(ui: &mut egui::Ui, cpu: &mut Cpu) {
+fn draw_register_view(ui: &mut egui::Ui, cpu: &CpuSnapshot) {
     ui.group(|ui| {
         ui.label("Register View");
 
@@ -573,13 +1236,157 @@ fn draw_register_view(ui: &mut egui::Ui, cpu: &mut Cpu) {
 
             ui.add_space(ui.available_width());
         });
+
+        ui.horizontal(|ui| {
+            ui.label("X");
+
+            ui.add_space(5.0);
+
+            ui.label(egui::RichText::from(format!("{:02X}", cpu.x_register)).monospace());
+
+            ui.add_space(5.0);
+
+            ui.label("Y");
+
+            ui.add_space(5.0);
+
+            ui.label(egui::RichText::from(format!("{:02X}", cpu.y_register)).monospace());
+
+            ui.add_space(ui.available_width());
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Stack Pointer");
+
+            ui.add_space(5.0);
+
+            ui.label(egui::RichText::from(format!("{:02X}", cpu.stack_pointer)).monospace());
+
+            ui.add_space(ui.available_width());
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Flags");
+
+            ui.add_space(5.0);
+
+            for (name, set) in [
+                ("N", cpu.status_register.negative),
+                ("V", cpu.status_register.overflow),
+                ("Z", cpu.status_register.zero),
+                ("C", cpu.status_register.carry),
+            ] {
+                ui.label(egui::RichText::from(name).monospace().color(if set {
+                    egui::Color32::BLACK
+                } else {
+                    egui::Color32::GRAY
+                }));
+            }
+
+            ui.add_space(ui.available_width());
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Effective Clock");
+
+            ui.add_space(5.0);
+
+            ui.label(egui::RichText::from(format!("{} Hz", cpu.effective_clock_speed)).monospace());
+
+            ui.add_space(ui.available_width());
+        });
     });
 }
 
+fn draw_trace(egui_ctx: &egui::Context, trace: &TraceBuffer, view: &mut TraceView) {
+    // Drain even while the window is closed, so the CPU's buffer doesn't go stale
+    for entry in trace.drain() {
+        if view.entries.len() == TRACE_HISTORY_LENGTH {
+            view.entries.pop_front();
+        }
+
+        view.entries.push_back((entry, entry.format()));
+    }
+
+    let mut open = view.open;
+
+    egui::Window::new("Trace")
+        .open(&mut open)
+        .default_size([420.0, 300.0])
+        .show(egui_ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut view.show_instructions, "Instructions");
+                ui.checkbox(&mut view.show_writes, "Writes");
+
+                ui.label("Filter");
+                ui.add(
+                    egui::TextEdit::singleline(&mut view.filter)
+                        .font(egui::TextStyle::Monospace)
+                        .desired_width(60.0),
+                );
+
+                if ui.button("Clear").clicked() {
+                    view.entries.clear();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut view.export_path)
+                        .font(egui::TextStyle::Monospace)
+                        .desired_width(200.0),
+                );
+
+                if ui.button("Export").clicked() {
+                    let mut text = String::new();
+                    for (_, line) in &view.entries {
+                        text.push_str(line);
+                        text.push('\n');
+                    }
+
+                    view.export_status = match std::fs::write(&view.export_path, text) {
+                        Ok(()) => format!("Wrote {} entries", view.entries.len()),
+                        Err(error) => error.to_string(),
+                    };
+                }
+
+                ui.label(&view.export_status);
+            });
+
+            ui.separator();
+
+            let filter = view.filter.to_uppercase();
+            let lines: Vec<&String> = view
+                .entries
+                .iter()
+                .filter(|(entry, _)| match entry.event {
+                    TraceEvent::Instruction { .. } => view.show_instructions,
+                    TraceEvent::Write { .. } => view.show_writes,
+                })
+                .filter(|(_, line)| line.contains(&filter))
+                .map(|(_, line)| line)
+                .collect();
+
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+            egui::ScrollArea::vertical()
+                .stick_to_bottom(true)
+                .auto_shrink([false, false])
+                .show_rows(ui, row_height, lines.len(), |ui, rows| {
+                    for line in &lines[rows] {
+                        ui.label(egui::RichText::from(line.as_str()).monospace());
+                    }
+                });
+        });
+
+    view.open = open;
+}
+
 pub fn draw_editor(
     editor_state: Arc<EguiState>,
     params: Arc<SixFiveParams>,
-    cpu: Arc<Mutex<Cpu>>,
+    snapshot: Arc<Mutex<triple_buffer::Output<CpuSnapshot>>>,
+    commands: Arc<CommandQueue>,
+    trace: Arc<TraceBuffer>,
 ) -> Option<Box<dyn Editor>> {
     create_egui_editor(
         editor_state,
@@ -588,25 +1395,36 @@ pub fn draw_editor(
         move |egui_ctx, setter, state| {
             egui_ctx.set_visuals(egui::Visuals::light());
 
-            egui::CentralPanel::default().show(egui_ctx, |ui| {
-                let mut cpu = cpu.lock().unwrap();
+            // The audio thread never takes this lock, it only guards against two editor instances
+            let mut snapshot_output = snapshot.lock().unwrap();
+            let cpu = snapshot_output.read();
 
+            egui::CentralPanel::default().show(egui_ctx, |ui| {
                 ui.columns(2, |columns| {
-                    draw_rom(&mut columns[0], &params, setter, state, &cpu);
-                    draw_ram(&mut columns[0], &cpu);
+                    draw_rom(&mut columns[0], &params, setter, state, cpu, &commands);
+                    draw_ram(
+                        &mut columns[0],
+                        cpu,
+                        &commands,
+                        &mut state.watch_address,
+                        &mut state.watchpoint,
+                    );
                     draw_instruction_pointer(
                         &mut columns[0],
                         &params,
                         setter,
-                        &mut cpu,
+                        cpu,
+                        &commands,
                         &mut state.clock_speed,
+                        &mut state.trace.open,
                     );
+                    draw_history(&mut columns[0], cpu, &commands);
 
-                    draw_audio_registers(&mut columns[1], &cpu);
+                    draw_audio_registers(&mut columns[1], cpu);
 
                     columns[1].columns(2, |columns| {
                         columns[0].vertical(|ui| {
-                            draw_overwrite_instruction_pointer(ui, &mut cpu);
+                            draw_overwrite_instruction_pointer(ui, &commands);
 
                             draw_enable_voices(ui, &params, setter);
                         });
@@ -614,11 +1432,15 @@ pub fn draw_editor(
                         columns[1].vertical(|ui| {
                             draw_trampoline_vectors(ui, &params, setter);
 
-                            draw_register_view(ui, &mut cpu);
+                            draw_register_view(ui, cpu);
                         });
                     });
                 });
             });
+
+            draw_disassembly(egui_ctx, &params, cpu, &mut state.show_disassembly);
+            draw_assembly(egui_ctx, &params, state, &commands);
+            draw_trace(egui_ctx, &trace, &mut state.trace);
         },
     )
 }
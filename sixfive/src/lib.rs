@@ -1,35 +1,58 @@
 use gui::draw_editor;
 use nih_plug::prelude::*;
 use std::sync::{Arc, Mutex};
+use triple_buffer::TripleBuffer;
 
-mod cpu;
 mod gui;
 mod params;
-mod sound;
+mod shared;
 
-use cpu::Cpu;
 use params::SixFiveParams;
+use shared::{CommandQueue, CpuSnapshot};
+use sixfive_core::{
+    interrupt::{MidiEvent, MidiEventKind},
+    scheduler::{Scheduler, Transport},
+    Machine,
+};
 
 pub struct SixFive {
     params: Arc<SixFiveParams>,
     sample_rate: f32,
 
-    cpu: Arc<Mutex<Cpu>>,
+    // Owned by the audio thread, the editor only sees snapshots and sends commands
+    machine: Machine,
+    snapshot: triple_buffer::Input<CpuSnapshot>,
+    editor_snapshot: Arc<Mutex<triple_buffer::Output<CpuSnapshot>>>,
+    commands: Arc<CommandQueue>,
 
-    samples_until_execute: f64,
+    scheduler: Scheduler,
+
+    // Instructions actually executed per second, measured for display in the editor
+    effective_clock_speed: u32,
+    instructions_measured: u64,
+    samples_measured: u64,
 }
 
 impl Default for SixFive {
     fn default() -> Self {
         let params = Arc::new(SixFiveParams::default());
+        let (snapshot, editor_snapshot) = TripleBuffer::default().split();
 
         Self {
             params: params.clone(),
             sample_rate: 1.0,
 
-            cpu: Arc::new(Mutex::new(Cpu::new(&params))),
+            machine: Machine::new(params.rom()),
+            snapshot,
+            // Only ever locked by the editor, never by the audio thread
+            editor_snapshot: Arc::new(Mutex::new(editor_snapshot)),
+            commands: Arc::new(CommandQueue::default()),
+
+            scheduler: Scheduler::default(),
 
-            samples_until_execute: 0.0,
+            effective_clock_speed: 0,
+            instructions_measured: 0,
+            samples_measured: 0,
         }
     }
 }
@@ -73,18 +96,23 @@ impl Plugin for SixFive {
     ) -> bool {
         self.sample_rate = buffer_config.sample_rate;
 
+        // Also called after the host loads a saved state, so this picks up the loaded ROM
+        self.machine.rom = self.params.rom();
+
         true
     }
 
     fn reset(&mut self) {
-        self.cpu.lock().unwrap().reset();
+        self.machine.reset();
     }
 
     fn editor(&self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
         draw_editor(
             self.params.editor_state.clone(),
             self.params.clone(),
-            self.cpu.clone(),
+            self.editor_snapshot.clone(),
+            self.commands.clone(),
+            self.machine.trace.clone(),
         )
     }
 
@@ -94,26 +122,32 @@ impl Plugin for SixFive {
         _aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        let mut next_event = context.next_event();
-        for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
-            let output = {
-                let mut cpu = self.cpu.lock().unwrap();
+        self.params.update_rom(&mut self.machine.rom);
 
-                if cpu.clock_running {
-                    if self.samples_until_execute <= 0.0 {
-                        cpu.execute();
+        while let Some(command) = self.commands.receive() {
+            command.apply(&mut self.machine);
+        }
 
-                        self.samples_until_execute +=
-                            (self.sample_rate as f64) / (self.params.clock_speed.value() as f64);
-                    }
+        let transport = context.transport();
+        let tempo = transport.tempo.unwrap_or(120.0);
 
-                    self.samples_until_execute -= 1.0;
-                } else {
-                    self.samples_until_execute = 0.0;
-                }
+        self.scheduler.begin_buffer(
+            buffer.samples(),
+            self.sample_rate as f64,
+            self.params.clock_frequency(tempo),
+            Transport {
+                tempo,
+                playing: transport.playing,
+                position: transport.pos_beats(),
+            },
+        );
 
-                cpu.sound.generate(self.sample_rate as f64)
-            };
+        self.machine.sound.quality = self.params.quality.value().oscillator_quality();
+        self.machine.sound.mode = self.params.emulation.value().sound_mode();
+
+        let mut next_event = context.next_event();
+        for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
+            let output = self.scheduler.process_sample(&mut self.machine);
 
             for sample in channel_samples {
                 *sample = output;
@@ -124,17 +158,50 @@ impl Plugin for SixFive {
                     break;
                 }
 
-                match event {
-                    NoteEvent::NoteOn { note, velocity, .. } => {}
-                    NoteEvent::NoteOff { note, .. } => {}
-                    NoteEvent::MidiCC { cc, value, .. } => {}
-                    _ => (),
+                let midi_event = match event {
+                    NoteEvent::NoteOn { note, velocity, .. } => Some(MidiEvent {
+                        kind: MidiEventKind::NoteOn,
+                        data_1: note,
+                        data_2: (velocity * 127.0).round() as u8,
+                    }),
+                    NoteEvent::NoteOff { note, velocity, .. } => Some(MidiEvent {
+                        kind: MidiEventKind::NoteOff,
+                        data_1: note,
+                        data_2: (velocity * 127.0).round() as u8,
+                    }),
+                    NoteEvent::MidiCC { cc, value, .. } => Some(MidiEvent {
+                        kind: MidiEventKind::ControlChange,
+                        data_1: cc,
+                        data_2: (value * 127.0).round() as u8,
+                    }),
+                    _ => None,
+                };
+
+                if let Some(midi_event) = midi_event {
+                    self.machine.queue_interrupt(midi_event);
                 }
 
                 next_event = context.next_event();
             }
         }
 
+        // Update the measured clock speed a few times per second
+        self.instructions_measured += self.scheduler.take_instructions_executed();
+        self.samples_measured += buffer.samples() as u64;
+        if self.samples_measured as f32 >= self.sample_rate / 4.0 {
+            let clock_speed = self.instructions_measured as f64 * (self.sample_rate as f64)
+                / (self.samples_measured as f64);
+            self.effective_clock_speed = clock_speed.round() as u32;
+
+            self.instructions_measured = 0;
+            self.samples_measured = 0;
+        }
+
+        let snapshot = self.snapshot.input_buffer();
+        snapshot.capture(&self.machine);
+        snapshot.effective_clock_speed = self.effective_clock_speed;
+        self.snapshot.publish();
+
         ProcessStatus::KeepAlive
     }
 }
@@ -1,5 +1,9 @@
 use nih_plug::prelude::*;
 use nih_plug_egui::EguiState;
+use sixfive_core::{
+    sound::{OscillatorQuality, SoundMode},
+    Rom,
+};
 use std::sync::{Arc, Mutex};
 
 #[derive(PartialEq, Copy, Clone, Enum)]
@@ -31,6 +35,71 @@ impl RomBank {
     }
 }
 
+#[derive(PartialEq, Copy, Clone, Enum)]
+pub enum ClockMode {
+    #[name = "Hz"]
+    Hertz,
+    #[name = "Ticks per Beat"]
+    TicksPerBeat,
+    #[name = "Ticks per 16th"]
+    TicksPerSixteenth,
+}
+
+impl ClockMode {
+    pub fn as_index(&self) -> usize {
+        match self {
+            ClockMode::Hertz => 0,
+            ClockMode::TicksPerBeat => 1,
+            ClockMode::TicksPerSixteenth => 2,
+        }
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(ClockMode::Hertz),
+            1 => Some(ClockMode::TicksPerBeat),
+            2 => Some(ClockMode::TicksPerSixteenth),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Enum)]
+pub enum Quality {
+    #[name = "Naive"]
+    Naive,
+    // Costs a little more CPU, but doesn't alias at high pitches
+    #[name = "Band-limited"]
+    BandLimited,
+}
+
+impl Quality {
+    pub fn oscillator_quality(&self) -> OscillatorQuality {
+        match self {
+            Quality::Naive => OscillatorQuality::Naive,
+            Quality::BandLimited => OscillatorQuality::BandLimited,
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Enum)]
+pub enum Emulation {
+    #[name = "Simple"]
+    Simple,
+    // Clocks the sound chip like the NES, so programs sound like the hardware
+    #[name = "Accurate"]
+    Accurate,
+}
+
+impl Emulation {
+    pub fn sound_mode(&self) -> SoundMode {
+        match self {
+            Emulation::Simple => SoundMode::Simple,
+            Emulation::Accurate => SoundMode::Accurate,
+        }
+    }
+}
+
 #[derive(Params)]
 pub struct TrampolineVectorParams {
     #[id = "state"]
@@ -42,15 +111,27 @@ pub struct SixFiveParams {
     #[persist = "editor-state"]
     pub editor_state: Arc<EguiState>,
 
+    // The bank the CPU starts in after a reset, programs can switch banks themselves
     #[id = "rom-bank-select"]
     pub rom_bank_select: EnumParam<RomBank>,
 
     #[persist = "rom-banks"]
     pub rom_banks: Mutex<[Vec<u16>; 4]>,
 
+    // Assembly source for each bank, kept so the project has readable code alongside the ROM
+    #[persist = "rom-sources"]
+    pub rom_sources: Mutex<[String; 4]>,
+
+    #[id = "clock-mode"]
+    pub clock_mode: EnumParam<ClockMode>,
+
     #[id = "clock-speed"]
     pub clock_speed: IntParam,
 
+    // Used instead of clock_speed when the clock is synced to the host tempo
+    #[id = "clock-ticks"]
+    pub clock_ticks: IntParam,
+
     // todo: instruction pointer overwrite as parameters?
     #[nested(array, group = "trampoline-vectors")]
     pub trampoline_vectors: [TrampolineVectorParams; 4],
@@ -66,28 +147,49 @@ pub struct SixFiveParams {
 
     #[id = "noise-enable"]
     pub noise_enable: BoolParam,
+
+    #[id = "quality"]
+    pub quality: EnumParam<Quality>,
+
+    // The accurate mode resamples the chip's output itself, so quality only affects simple mode
+    #[id = "emulation"]
+    pub emulation: EnumParam<Emulation>,
 }
 
 impl SixFiveParams {
-    pub fn read_rom(&self, address: u8) -> u8 {
-        let bank_index = self.rom_bank_select.value().as_index();
-        let bank = &self.rom_banks.lock().unwrap()[bank_index];
-
-        if address & 0b1 == 0 {
-            (bank[address as usize / 2] >> 8) as u8
-        } else {
-            bank[address as usize / 2] as u8
+    // Instructions per second, given the current host tempo
+    pub fn clock_frequency(&self, tempo: f64) -> f64 {
+        match self.clock_mode.value() {
+            ClockMode::Hertz => self.clock_speed.value() as f64,
+            ClockMode::TicksPerBeat => self.clock_ticks.value() as f64 * tempo / 60.0,
+            ClockMode::TicksPerSixteenth => self.clock_ticks.value() as f64 * 4.0 * tempo / 60.0,
         }
     }
 
-    pub fn read_trampoline_vector(&self, address: u8) -> u8 {
-        match address {
-            0xFC => self.trampoline_vectors[0].state.value() as u8,
-            0xFD => self.trampoline_vectors[1].state.value() as u8,
-            0xFE => self.trampoline_vectors[2].state.value() as u8,
-            0xFF => self.trampoline_vectors[3].state.value() as u8,
-            _ => panic!("invalid trampoline vector address"),
+    // A copy of the whole ROM. This locks the banks, so it's only used when the plugin is
+    // initialized: after that, the editor sends its edits to the machine's copy as commands.
+    pub fn rom(&self) -> Rom {
+        let mut rom = Rom::default();
+        for (bank, words) in self.rom_banks.lock().unwrap().iter().enumerate() {
+            rom.load_bank(bank, words);
         }
+
+        self.update_rom(&mut rom);
+        rom
+    }
+
+    // The trampoline vectors and power-on bank are ordinary parameters, so these can be
+    // picked up on the audio thread every buffer
+    pub fn update_rom(&self, rom: &mut Rom) {
+        for (vector, param) in rom
+            .trampoline_vectors
+            .iter_mut()
+            .zip(&self.trampoline_vectors)
+        {
+            *vector = param.state.value() as u8;
+        }
+
+        rom.power_on_bank = self.rom_bank_select.value().as_index();
     }
 }
 
@@ -99,6 +201,7 @@ impl Default for SixFiveParams {
             rom_bank_select: EnumParam::new("ROM Bank Selection", RomBank::A),
 
             rom_banks: Mutex::new([vec![0; 64], vec![0; 64], vec![0; 64], vec![0; 64]]),
+            rom_sources: Mutex::new(Default::default()),
 
             clock_speed: IntParam::new(
                 "Clock Speed",
@@ -110,6 +213,10 @@ impl Default for SixFiveParams {
             )
             .with_smoother(SmoothingStyle::Logarithmic(0.1)),
 
+            clock_mode: EnumParam::new("Clock Mode", ClockMode::Hertz),
+
+            clock_ticks: IntParam::new("Clock Ticks", 4, IntRange::Linear { min: 1, max: 256 }),
+
             // todo: instruction pointer overwrite?
             trampoline_vectors: [
                 TrampolineVectorParams {
@@ -130,6 +237,9 @@ impl Default for SixFiveParams {
             square_wave_2_enable: BoolParam::new("Square Wave 2 Enable", true),
             triangle_wave_enable: BoolParam::new("Triangle Wave Enable", true),
             noise_enable: BoolParam::new("Noise Enable", true),
+
+            quality: EnumParam::new("Oscillator Quality", Quality::Naive),
+            emulation: EnumParam::new("Sound Emulation", Emulation::Simple),
         }
     }
 }
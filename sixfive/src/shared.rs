@@ -0,0 +1,160 @@
+use crossbeam::queue::ArrayQueue;
+
+use sixfive_core::{
+    bus::Peripheral,
+    debug::{AddressMap, Breakpoint, BreakpointMap, WatchHit, Watchpoint},
+    machine::{FaultRecord, StatusRegister},
+    sound::ChannelRegisters,
+    Machine,
+};
+
+// The audio thread owns the machine. The editor never touches it directly: it reads the most
+// recent CpuSnapshot from a triple buffer, and sends EditorCommands back through a queue.
+
+const COMMAND_QUEUE_LENGTH: usize = 64;
+
+#[derive(Clone, Copy)]
+pub enum EditorCommand {
+    Reset,
+    Run,
+    Stop,
+    Pause,
+    SetInstructionPointer(u8),
+    ClearFault,
+    Step,
+    StepOver,
+    // Bank and address, for each of these
+    RunTo(usize, u8),
+    ToggleBreakpoint(usize, u8),
+    SetBreakpoint(usize, u8, Option<Breakpoint>),
+    SetWatchpoint(u8, Watchpoint),
+    StepBack,
+    // Instruction count to go back (or forward) to
+    Seek(u64),
+    SetHistoryInterval(u32),
+    // The editor's copy of the ROM is in the parameters, this keeps the machine's in step
+    SetRomBank(usize, [u16; 0x40]),
+}
+
+impl EditorCommand {
+    pub fn apply(self, machine: &mut Machine) {
+        match self {
+            EditorCommand::Reset => machine.reset(),
+            EditorCommand::Run => machine.run(),
+            EditorCommand::Stop => {
+                machine.reset();
+                machine.clock_running = false;
+            }
+            EditorCommand::Pause => machine.pause(),
+            EditorCommand::SetInstructionPointer(address) => {
+                machine.instruction_pointer = address;
+                machine.clock_running = true;
+            }
+            EditorCommand::ClearFault => machine.fault = None,
+            EditorCommand::Step => machine.step_instruction(),
+            EditorCommand::StepOver => machine.step_over(),
+            EditorCommand::RunTo(bank, address) => machine.run_to(bank, address),
+            EditorCommand::ToggleBreakpoint(bank, address) => {
+                machine.debugger.toggle_breakpoint(bank, address)
+            }
+            EditorCommand::SetBreakpoint(bank, address, breakpoint) => {
+                machine.debugger.set_breakpoint(bank, address, breakpoint)
+            }
+            EditorCommand::SetWatchpoint(address, watchpoint) => {
+                machine.debugger.set_watchpoint(address, watchpoint)
+            }
+            EditorCommand::StepBack => machine.step_back(),
+            EditorCommand::Seek(instruction) => machine.seek(instruction),
+            EditorCommand::SetHistoryInterval(interval) => {
+                machine.history.interval = interval.max(1)
+            }
+            EditorCommand::SetRomBank(bank, words) => machine.rom.banks[bank] = words,
+        }
+    }
+}
+
+pub struct CommandQueue {
+    queue: ArrayQueue<EditorCommand>,
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        Self {
+            queue: ArrayQueue::new(COMMAND_QUEUE_LENGTH),
+        }
+    }
+}
+
+impl CommandQueue {
+    pub fn send(&self, command: EditorCommand) {
+        // If the audio thread isn't running, there's no point in queueing up more commands
+        let _ = self.queue.push(command);
+    }
+
+    pub fn receive(&self) -> Option<EditorCommand> {
+        self.queue.pop()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CpuSnapshot {
+    pub accumulator: u8,
+    pub x_register: u8,
+    pub y_register: u8,
+    pub instruction_pointer: u8,
+    pub rom_bank: usize,
+    pub status_register: StatusRegister,
+    pub stack_pointer: u8,
+    pub clock_running: bool,
+    pub fault: Option<FaultRecord>,
+    pub breakpoints: BreakpointMap,
+    pub watchpoints: AddressMap<Watchpoint>,
+    pub watch_hit: Option<WatchHit>,
+    pub last_writers: AddressMap<Option<(usize, u8)>>,
+
+    pub instructions_executed: u64,
+    pub history_range: Option<(u64, u64)>,
+    pub history_interval: u32,
+
+    pub ram: [u8; 0x20],
+    pub sound_registers: [u8; 0x10],
+    pub square_wave_1: ChannelRegisters,
+    pub square_wave_2: ChannelRegisters,
+    pub triangle_wave: ChannelRegisters,
+    pub noise: ChannelRegisters,
+
+    // Instructions actually executed per second, measured by the audio thread
+    pub effective_clock_speed: u32,
+}
+
+impl CpuSnapshot {
+    // Updates the snapshot in place, so that publishing doesn't allocate on the audio thread
+    pub fn capture(&mut self, machine: &Machine) {
+        self.accumulator = machine.accumulator;
+        self.x_register = machine.x_register;
+        self.y_register = machine.y_register;
+        self.instruction_pointer = machine.instruction_pointer;
+        self.rom_bank = machine.rom_bank;
+        self.status_register = machine.status_register;
+        self.stack_pointer = machine.stack_pointer;
+        self.clock_running = machine.clock_running;
+        self.fault = machine.fault;
+        self.breakpoints = machine.debugger.breakpoints;
+        self.watchpoints = machine.debugger.watchpoints;
+        self.watch_hit = machine.debugger.watch_hit;
+        self.last_writers = machine.debugger.last_writers;
+
+        self.instructions_executed = machine.history.instructions_executed;
+        self.history_range = machine.history.range();
+        self.history_interval = machine.history.interval;
+
+        self.ram = machine.ram;
+        for (i, register) in self.sound_registers.iter_mut().enumerate() {
+            *register = machine.sound.read(0xA0 + i as u8).unwrap_or(0);
+        }
+        self.square_wave_1 = *machine.sound.square_wave_1.registers();
+        self.square_wave_2 = *machine.sound.square_wave_2.registers();
+        self.triangle_wave = *machine.sound.triangle_wave.registers();
+        self.noise = *machine.sound.noise.registers();
+    }
+}